@@ -1,10 +1,35 @@
 use rand::{RngCore};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use sha2::{Digest, Sha256};
 use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use crate::encoding::{parse_hex, to_hex};
+use crate::target::Target;
+use crate::transaction::Transaction;
 
 pub const DIFFICULTY: u32 = 10;
 
+/// Version of the blocks built by this code.
+pub const BLOCK_VERSION: u32 = 3;
+
+/// Current time, in milliseconds since the Unix epoch, as used in
+/// [`Block::timestamp`].
+pub fn timestamp_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+/// Maximum length, in bytes, of a miner name.
+pub const MAX_MINER_NAME_LEN: usize = 64;
+
+/// Maximum number of transactions in a block.
+pub const MAX_BLOCK_TRANSACTIONS: usize = 1000;
+
 #[derive(Default)]
 pub struct BlockHasher {
     id: u64,
@@ -34,20 +59,283 @@ impl std::hash::Hasher for BlockHasher {
 pub type BlockIdHasher = std::hash::BuildHasherDefault<BlockHasher>;
 
 // @Student Remove if you're not using it.
-pub type BlockHashSet = HashSet<u64, BlockIdHasher>;
+pub type BlockHashSet = HashSet<BlockHash, BlockIdHasher>;
+
+/// SHA-256 hash of a block, which identifies it.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BlockHash(pub [u8; 32]);
+
+impl BlockHash {
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl std::hash::Hash for BlockHash {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        // BlockHasher uses the id as is. Proofs of work make the first bytes
+        // of a block hash zeros, but its last 8 bytes are uniformly
+        // distributed.
+        let mut id = [0; 8];
+        id.copy_from_slice(&self.0[24..]);
+        state.write_u64(u64::from_le_bytes(id));
+    }
+}
+
+impl From<[u8; 32]> for BlockHash {
+    fn from(hash: [u8; 32]) -> Self {
+        BlockHash(hash)
+    }
+}
+
+impl TryFrom<&[u8]> for BlockHash {
+    type Error = std::array::TryFromSliceError;
+
+    fn try_from(hash: &[u8]) -> Result<Self, Self::Error> {
+        Ok(BlockHash(hash.try_into()?))
+    }
+}
 
+impl AsRef<[u8]> for BlockHash {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Display for BlockHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&to_hex(&self.0))
+    }
+}
+
+impl fmt::Debug for BlockHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BlockHash({})", self)
+    }
+}
+
+/// Error returned when parsing a [`BlockHash`] from a string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseBlockHashError;
+
+impl fmt::Display for ParseBlockHashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a block hash is made of 64 hexadecimal digits")
+    }
+}
+
+impl std::error::Error for ParseBlockHashError {}
+
+impl FromStr for BlockHash {
+    type Err = ParseBlockHashError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_hex(s).map(BlockHash).ok_or(ParseBlockHashError)
+    }
+}
+
+impl Serialize for BlockHash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for BlockHash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+
+/// A proof of work hash function. A block is valid if the hash of its
+/// header meets its target, see [`Block::pow_check`].
+pub trait ProofOfWork: fmt::Debug + Send + Sync {
+    /// Hashes a block header, as encoded by [`Block::encode_header`]
+    fn hash(&self, header: &[u8]) -> [u8; 32];
+}
+
+/// A single SHA-256, like the hash identifying the blocks.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SingleSha256;
+
+impl ProofOfWork for SingleSha256 {
+    fn hash(&self, header: &[u8]) -> [u8; 32] {
+        Sha256::digest(header).into()
+    }
+}
+
+/// SHA-256 applied twice, as in Bitcoin.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DoubleSha256;
+
+impl ProofOfWork for DoubleSha256 {
+    fn hash(&self, header: &[u8]) -> [u8; 32] {
+        Sha256::digest(Sha256::digest(header)).into()
+    }
+}
+
+/// BLAKE3, several times faster than SHA-256 on CPUs.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Blake3;
+
+impl ProofOfWork for Blake3 {
+    fn hash(&self, header: &[u8]) -> [u8; 32] {
+        blake3::hash(header).into()
+    }
+}
+
+/// Memory-hard scrypt with the parameters of Litecoin (N = 1024, r = 1,
+/// p = 1): each hash needs 128 KiB of memory, and so does each check.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Scrypt;
+
+impl ProofOfWork for Scrypt {
+    fn hash(&self, header: &[u8]) -> [u8; 32] {
+        let params = scrypt::Params::new(10, 1, 1, 32).unwrap();
+        let mut hash = [0; 32];
+        scrypt::scrypt(header, header, &params, &mut hash).unwrap();
+        hash
+    }
+}
 
 #[derive(Clone, Default, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
 pub struct Block {
+    /// Version of the block format, [`BLOCK_VERSION`] for now.
+    pub version: u32,
     /// Hash of the parent block
     pub parent_hash: Vec<u8>,
-    /// Miner's (unique) identity. We don't use asymmetric cryptography for this simple exercise.
+    /// Root of the Merkle tree of `transactions`, see [`merkle_root`].
+    pub merkle_root: [u8; 32],
+    /// Human readable alias of the miner, may be empty. The miner is
+    /// identified by `public_key`.
     pub miner: String,
+    /// Ed25519 public key of the miner, see [`crate::identity`].
+    pub public_key: Vec<u8>,
     /// Random value such the hash value of this block is valid.
     pub nonce: u64,
     /// Dancemove chosen by the miner. That's the very strong incentive explaining
     /// why everyone one wants to mine on this blockchain.
     pub dancemove: DanceMove,
+    /// Milliseconds since the Unix epoch at which the block was built.
+    pub timestamp: u64,
+    /// Distance to the genesis block, which has height 0.
+    pub height: u64,
+    /// Target the hash of this block must meet, in compact form, see
+    /// [`Target`].
+    pub bits: u32,
+    /// Ed25519 signature of the header by `public_key`, see
+    /// [`Block::encode_header`]. Not part of the block hash.
+    pub signature: Vec<u8>,
+    /// Body of the block, committed to by `merkle_root`.
+    pub transactions: Vec<Transaction>,
+}
+
+/// Root of the Merkle tree of transactions, built as in RFC 6962: leaves
+/// are hashed with a 0x00 prefix, nodes with a 0x01 prefix, and a tree of
+/// n leaves is split after the largest power of two lower than n. The root
+/// of no transaction is the SHA-256 of nothing.
+pub fn merkle_root(transactions: &[Transaction]) -> [u8; 32] {
+    match transactions {
+        [] => Sha256::digest([]).into(),
+        [transaction] => merkle_leaf(transaction),
+        _ => {
+            let split = 1 << (transactions.len() - 1).ilog2();
+            merkle_node(
+                &merkle_root(&transactions[..split]),
+                &merkle_root(&transactions[split..]),
+            )
+        }
+    }
+}
+
+/// Hash of a leaf of the Merkle tree, see [`merkle_root`]
+pub fn merkle_leaf(transaction: &Transaction) -> [u8; 32] {
+    Sha256::new()
+        .chain_update([0x00])
+        .chain_update(transaction.encode())
+        .finalize()
+        .into()
+}
+
+/// Hash of an inner node of the Merkle tree, see [`merkle_root`]
+pub fn merkle_node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    Sha256::new()
+        .chain_update([0x01])
+        .chain_update(left)
+        .chain_update(right)
+        .finalize()
+        .into()
+}
+
+/// Proof that a transaction is a leaf of a Merkle tree: the hashes of the
+/// siblings of the nodes on the path from the leaf to the root, from the
+/// bottom up, as the audit paths of RFC 6962.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MerkleProof {
+    /// Position of the transaction in the block
+    pub index: u64,
+    /// Number of transactions of the block
+    pub leaf_count: u64,
+    pub path: Vec<[u8; 32]>,
+}
+
+/// Proof that `transactions[index]` is committed to by
+/// [`merkle_root`]`(transactions)`, `None` if `index` is out of bounds
+pub fn merkle_proof(transactions: &[Transaction], index: usize) -> Option<MerkleProof> {
+    fn audit_path(transactions: &[Transaction], index: usize, path: &mut Vec<[u8; 32]>) {
+        if transactions.len() <= 1 {
+            return;
+        }
+        let split = 1 << (transactions.len() - 1).ilog2();
+        if index < split {
+            audit_path(&transactions[..split], index, path);
+            path.push(merkle_root(&transactions[split..]));
+        } else {
+            audit_path(&transactions[split..], index - split, path);
+            path.push(merkle_root(&transactions[..split]));
+        }
+    }
+
+    if index >= transactions.len() {
+        return None;
+    }
+    let mut proof = MerkleProof {
+        index: index as u64,
+        leaf_count: transactions.len() as u64,
+        path: Vec::new(),
+    };
+    audit_path(transactions, index, &mut proof.path);
+    Some(proof)
+}
+
+impl MerkleProof {
+    /// Root of the tree `transaction` is in, according to the proof.
+    /// `None` if the path doesn't fit the position of the transaction.
+    pub fn root(&self, transaction: &Transaction) -> Option<[u8; 32]> {
+        fn fold(index: u64, count: u64, leaf: [u8; 32], path: &[[u8; 32]]) -> Option<[u8; 32]> {
+            if count == 1 {
+                return path.is_empty().then_some(leaf);
+            }
+            let (sibling, path) = path.split_last()?;
+            let split = 1 << (count - 1).ilog2();
+            if index < split {
+                Some(merkle_node(&fold(index, split, leaf, path)?, sibling))
+            } else {
+                Some(merkle_node(sibling, &fold(index - split, count - split, leaf, path)?))
+            }
+        }
+
+        if self.index >= self.leaf_count {
+            return None;
+        }
+        fold(self.index, self.leaf_count, merkle_leaf(transaction), &self.path)
+    }
+
+    /// Checks that `transaction` is committed to by the Merkle root `root`
+    pub fn verify(&self, transaction: &Transaction, root: &[u8; 32]) -> bool {
+        self.root(transaction).as_ref() == Some(root)
+    }
 }
 
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
@@ -59,73 +347,203 @@ pub enum DanceMove {
     A = 4,
 }
 
+impl DanceMove {
+    pub const ALL: [DanceMove; 4] = [DanceMove::Y, DanceMove::M, DanceMove::C, DanceMove::A];
+}
+
 
 
 
 impl Block {
+    /// Creates an unsigned block without transactions, with a timestamp,
+    /// height and bits of 0
     pub fn new(parent_hash: Vec<u8>, miner: String, nonce: u64, dancemove: DanceMove) -> Self {
         Block{
+            version: BLOCK_VERSION,
             parent_hash,
+            merkle_root: merkle_root(&[]),
             miner,
+            public_key: Vec::new(),
             nonce,
-            dancemove
+            dancemove,
+            timestamp: 0,
+            height: 0,
+            bits: 0,
+            signature: Vec::new(),
+            transactions: Vec::new(),
         }
     }
 
-    /// Computes the hash of self
-    pub fn hash_block(&self) -> [u8; 32] {
-        let mut hasher = Sha256::new();
+    /// Sets the body of the block, and the Merkle root committing to it
+    pub fn set_transactions(&mut self, transactions: Vec<Transaction>) {
+        self.merkle_root = merkle_root(&transactions);
+        self.transactions = transactions;
+    }
+
+    /// Copy of the block without its transactions, which still commits to
+    /// them through its Merkle root and has the same hash
+    pub fn header(&self) -> Block {
+        Block {
+            transactions: Vec::new(),
+            ..self.clone()
+        }
+    }
 
-        hasher.update(&self.parent_hash);
+    /// Creates a block to be mined on top of `parent`
+    pub fn new_child(
+        parent: &Block,
+        miner: String,
+        dancemove: DanceMove,
+        timestamp: u64,
+        bits: u32,
+    ) -> Self {
+        Block {
+            timestamp,
+            height: parent.height + 1,
+            bits,
+            ..Block::new(parent.hash_block().to_vec(), miner, 0, dancemove)
+        }
+    }
+
+    /// Computes the hash of self, a single SHA-256 whatever the proof of
+    /// work algorithm
+    pub fn hash_block(&self) -> [u8; 32] {
+        Sha256::digest(self.encode_header()).into()
+    }
 
-        hasher.update(self.miner.as_bytes());
+    /// Computes the proof of work hash of self
+    pub fn pow_hash(&self, pow: &dyn ProofOfWork) -> [u8; 32] {
+        pow.hash(&self.encode_header())
+    }
 
-        hasher.update(&self.nonce.to_le_bytes());
+    /// Computes the hash of self, which identifies the block
+    pub fn block_hash(&self) -> BlockHash {
+        BlockHash(self.hash_block())
+    }
 
-        hasher.update(&[self.dancemove as u8]);
+    /// Hash of the parent block, `None` for a genesis block or if
+    /// `parent_hash` isn't a valid hash
+    pub fn parent(&self) -> Option<BlockHash> {
+        BlockHash::try_from(self.parent_hash.as_slice()).ok()
+    }
 
-        hasher.finalize().into()
+    /// Decoded [`Block::bits`], `None` if they aren't a valid compact target
+    pub fn target(&self) -> Option<Target> {
+        Target::from_compact(self.bits)
     }
 
-    /// Solves the block finding a nonce that hashes the block to
-    /// a hash value starting with `difficulty` bits set to 0. Returns the
-    /// hash value of the block stored in a Vec.
+    /// Solves the block finding a nonce that hashes the block, with
+    /// `pow`, to a hash value lower than or equal to `target`. Returns the
+    /// proof of work hash value of the block stored in a Vec.
     pub fn solve_block<R: RngCore>(
         &mut self,
         rng: &mut R,
-        difficulty: u32,
+        pow: &dyn ProofOfWork,
+        target: &Target,
         max_iteration: Option<u64>,
     ) -> Option<Vec<u8>> {
         for _ in 0..max_iteration.unwrap_or(u64::MAX) {
             self.nonce = rng.next_u64();
-            let hash = self.hash_block();
+            let hash = self.pow_hash(pow);
 
-            if self.pow_check(&hash, difficulty) {
+            if self.pow_check(&hash, target) {
                 return Some(hash.to_vec());
             }
         }
         None
     }
 
-    /// Checks if the proof of work is correct
-    pub fn pow_check(&self, hash: &[u8], difficulty: u32) -> bool {
-        let mut leading_bits = 0;
-
-        for byte in hash {
-            if *byte == 0 {
-                leading_bits += 8;
-            } else {
-                leading_bits += byte.leading_zeros();
-                break;
+    /// Solves the block like [`Block::solve_block`], on `threads` threads.
+    /// The nonce space is split in `threads` equal ranges, starting at a
+    /// random nonce, each thread going through its own range. All the
+    /// threads stop as soon as one of them finds a solution, or as soon as
+    /// `cancel` is set. `max_iteration` bounds the total number of hashes.
+    pub fn solve_block_parallel<R: RngCore>(
+        &mut self,
+        rng: &mut R,
+        pow: &dyn ProofOfWork,
+        target: &Target,
+        threads: usize,
+        max_iteration: Option<u64>,
+        cancel: Option<&AtomicBool>,
+    ) -> (Option<Vec<u8>>, SolveStats) {
+        let threads = threads.max(1) as u64;
+        let start_nonce = rng.next_u64();
+        let range_len = u64::MAX / threads;
+        let iterations_per_thread = max_iteration.map(|max| max.div_ceil(threads));
+
+        let found = AtomicBool::new(false);
+        let hashes = AtomicU64::new(0);
+        let solution = Mutex::new(None);
+        let start = Instant::now();
+
+        std::thread::scope(|scope| {
+            for i in 0..threads {
+                let mut block = self.clone();
+                let (found, hashes, solution) = (&found, &hashes, &solution);
+                let cancelled = || cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed));
+                let first_nonce = start_nonce.wrapping_add(i * range_len);
+                let iterations = iterations_per_thread.unwrap_or(range_len);
+
+                scope.spawn(move || {
+                    let mut done = 0;
+                    while done < iterations && !found.load(Ordering::Relaxed) && !cancelled() {
+                        block.nonce = first_nonce.wrapping_add(done);
+                        done += 1;
+                        let hash = block.pow_hash(pow);
+
+                        if block.pow_check(&hash, target) {
+                            if !found.swap(true, Ordering::Relaxed) {
+                                *solution.lock().unwrap() = Some((block.nonce, hash.to_vec()));
+                            }
+                            break;
+                        }
+                    }
+                    hashes.fetch_add(done, Ordering::Relaxed);
+                });
             }
-        }
+        });
 
-        leading_bits >= difficulty
+        let solution = solution.into_inner().unwrap();
+        let stats = SolveStats {
+            hashes: hashes.into_inner(),
+            elapsed: start.elapsed(),
+            cancelled: solution.is_none() && cancel.is_some_and(|c| c.load(Ordering::Relaxed)),
+        };
+        let solution = solution.map(|(nonce, hash)| {
+            self.nonce = nonce;
+            hash
+        });
+        (solution, stats)
+    }
+
+    /// Checks if the proof of work is correct: `hash` is a 32 bytes hash
+    /// lower than or equal to `target`
+    pub fn pow_check(&self, hash: &[u8], target: &Target) -> bool {
+        <&[u8; 32]>::try_from(hash).is_ok_and(|hash| target.is_met_by(hash))
     }
 
     pub fn is_genesis(&self, _difficulty: u32) -> bool {
 
-        self.parent_hash.is_empty() && self.miner == "Genesis".to_string()
+        self.parent_hash.is_empty() && self.miner == "Genesis" && self.height == 0
+    }
+}
+
+/// Statistics of a nonce search, see [`Block::solve_block_parallel`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SolveStats {
+    /// Number of hashes computed by all the threads
+    pub hashes: u64,
+    /// Duration of the search
+    pub elapsed: Duration,
+    /// True if the search was cancelled before finding a solution
+    pub cancelled: bool,
+}
+
+impl SolveStats {
+    /// Hashes per second
+    pub fn hashrate(&self) -> f64 {
+        self.hashes as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
     }
 }
 
@@ -138,6 +556,7 @@ impl crate::simpletree::Parenting for Block {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::transaction::Payload;
     use rand::rngs::StdRng;
     use rand::Rng;
     use rand::SeedableRng;
@@ -149,18 +568,23 @@ mod tests {
             miner: "test".to_string(),
             nonce: 0,
             dancemove: DanceMove::C,
+            ..Default::default()
         };
 
         // Test case where hash has sufficient leading zeros
-        let hash_with_zeros = vec![0x00, 0x00, 0x00, 0xFF];
-        assert!(block.pow_check(&hash_with_zeros, 24));
+        let mut hash_with_zeros = vec![0xFF; 32];
+        hash_with_zeros[..3].fill(0);
+        assert!(block.pow_check(&hash_with_zeros, &Target::from_leading_zeros(24)));
 
         // Test case with insufficient zeros
-        let hash_without_zeros = vec![0xFF, 0xFF, 0xFF, 0xFF];
-        assert!(!block.pow_check(&hash_without_zeros, 1));
+        let hash_without_zeros = vec![0xFF; 32];
+        assert!(!block.pow_check(&hash_without_zeros, &Target::from_leading_zeros(1)));
 
         // Test edge case (difficulty = 0)
-        assert!(block.pow_check(&hash_without_zeros, 0));
+        assert!(block.pow_check(&hash_without_zeros, &Target::MAX));
+
+        // Not a hash
+        assert!(!block.pow_check(&[0x00; 4], &Target::MAX));
     }
 
     #[test]
@@ -170,6 +594,7 @@ mod tests {
             miner: "test".to_string(),
             nonce: 0,
             dancemove: DanceMove::Y,
+            ..Default::default()
         };
 
         // Use a seeded Rng for deterministic testing
@@ -183,22 +608,217 @@ mod tests {
                 block.dancemove = DanceMove::M;
             }
 
-            let hash = block.solve_block(&mut rng, difficulty, None).unwrap();
+            let target = Target::from_leading_zeros(difficulty);
+            let hash = block.solve_block(&mut rng, &SingleSha256, &target, None).unwrap();
 
             // Ensure the solved hash meets the difficulty
-            assert!(block.pow_check(&hash, difficulty));
+            assert!(block.pow_check(&hash, &target));
 
             // Ensure nonce changed
             assert_ne!(block.nonce, 0);
         }
     }
 
+    #[test]
+    fn test_solve_block_parallel() {
+        let mut block = Block::new(vec![], "test".to_string(), 0, DanceMove::M);
+        let mut rng = StdRng::seed_from_u64(42);
+
+        let target = Target::from_leading_zeros(10);
+        let (hash, stats) = block.solve_block_parallel(&mut rng, &DoubleSha256, &target, 4, None, None);
+        let hash = hash.unwrap();
+        assert!(block.pow_check(&hash, &target));
+        assert_eq!(hash, block.pow_hash(&DoubleSha256).to_vec());
+        assert!(stats.hashes > 0);
+        assert!(stats.hashrate() > 0.0);
+
+        // Impossible difficulty, the search stops after max_iteration hashes
+        let impossible = Target::from_leading_zeros(256);
+        let (hash, stats) =
+            block.solve_block_parallel(&mut rng, &SingleSha256, &impossible, 3, Some(300), None);
+        assert_eq!(hash, None);
+        assert_eq!(stats.hashes, 300);
+        assert!(!stats.cancelled);
+    }
+
+    #[test]
+    fn test_solve_block_cancelled() {
+        let mut block = Block::new(vec![], "test".to_string(), 0, DanceMove::M);
+        let mut rng = StdRng::seed_from_u64(42);
+        let cancel = AtomicBool::new(false);
+
+        let (hash, stats) = std::thread::scope(|scope| {
+            let solver = scope.spawn(|| {
+                let impossible = Target::from_leading_zeros(256);
+                block.solve_block_parallel(&mut rng, &SingleSha256, &impossible, 2, None, Some(&cancel))
+            });
+            std::thread::sleep(Duration::from_millis(50));
+            cancel.store(true, Ordering::Relaxed);
+            solver.join().unwrap()
+        });
+
+        assert_eq!(hash, None);
+        assert!(stats.cancelled);
+    }
+
+    #[test]
+    fn test_block_hash_hex() {
+        let block = Block::new(vec![], "test".to_string(), 42, DanceMove::Y);
+        let hash = block.block_hash();
+        assert_eq!(hash.as_bytes(), &block.hash_block());
+
+        let hex = hash.to_string();
+        assert_eq!(hex.len(), 64);
+        assert_eq!(hex.parse::<BlockHash>(), Ok(hash));
+        assert_eq!("00ff".parse::<BlockHash>(), Err(ParseBlockHashError));
+        assert_eq!("zz".repeat(32).parse::<BlockHash>(), Err(ParseBlockHashError));
+
+        let mut bytes = [0; 32];
+        bytes[0] = 0xab;
+        bytes[31] = 0x01;
+        let expected = format!("ab{}01", "00".repeat(30));
+        assert_eq!(BlockHash(bytes).to_string(), expected);
+
+        // Serialized as a hex string
+        let json = serde_json::to_string(&BlockHash(bytes)).unwrap();
+        assert_eq!(json, format!("\"{}\"", expected));
+        assert_eq!(serde_json::from_str::<BlockHash>(&json).unwrap(), BlockHash(bytes));
+    }
+
+    #[test]
+    fn test_block_hash_set_with_proofs_of_work() {
+        use std::hash::BuildHasher;
+
+        // Hashes meeting a target start with zeros
+        let hashes: Vec<BlockHash> = (0..20_000u64)
+            .map(|i| {
+                let mut bytes = [0; 32];
+                bytes[8..].copy_from_slice(&Sha256::digest(i.to_le_bytes())[8..]);
+                BlockHash(bytes)
+            })
+            .collect();
+        let ids: HashSet<u64> = hashes.iter().map(|hash| BlockIdHasher::default().hash_one(hash)).collect();
+        assert_eq!(ids.len(), hashes.len());
+
+        let set: BlockHashSet = hashes.iter().copied().collect();
+        assert_eq!(set.len(), hashes.len());
+        assert!(hashes.iter().all(|hash| set.contains(hash)));
+    }
+
+    #[test]
+    fn test_header_fields_are_hashed() {
+        let parent = Block::new(vec![], "Genesis".to_string(), 0, DanceMove::Y);
+        let block = Block::new_child(&parent, "test".to_string(), DanceMove::A, 1000, 10);
+        assert_eq!(block.parent_hash, parent.hash_block().to_vec());
+        assert_eq!(block.height, 1);
+
+        let hash = block.hash_block();
+        let changes: [fn(&mut Block); 5] = [
+            |b| b.version += 1,
+            |b| b.timestamp += 1,
+            |b| b.height += 1,
+            |b| b.bits += 1,
+            |b| b.set_transactions(vec![Transaction::new(vec![1; 32], 0, Payload::Data(vec![]))]),
+        ];
+        for change in changes {
+            let mut changed = block.clone();
+            change(&mut changed);
+            assert_ne!(changed.hash_block(), hash);
+        }
+    }
+
+    #[test]
+    fn test_merkle_root() {
+        let txs: Vec<Transaction> = (0..5)
+            .map(|nonce| Transaction::new(vec![1; 32], nonce, Payload::Data(vec![])))
+            .collect();
+        let leaves: Vec<[u8; 32]> = txs.iter().map(merkle_leaf).collect();
+        let node = |left, right| merkle_node(&left, &right);
+
+        assert_eq!(
+            BlockHash(merkle_root(&[])).to_string(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(merkle_root(&txs[..1]), leaves[0]);
+        assert_eq!(merkle_root(&txs[..2]), node(leaves[0], leaves[1]));
+        // Not balanced: the last leaf isn't duplicated
+        assert_eq!(
+            merkle_root(&txs[..3]),
+            node(node(leaves[0], leaves[1]), leaves[2])
+        );
+        assert_eq!(
+            merkle_root(&txs),
+            node(
+                node(node(leaves[0], leaves[1]), node(leaves[2], leaves[3])),
+                leaves[4]
+            )
+        );
+        let mut changed = txs.clone();
+        changed[4].nonce += 1;
+        assert_ne!(merkle_root(&changed), merkle_root(&txs));
+    }
+
+    #[test]
+    fn test_merkle_proof() {
+        let txs: Vec<Transaction> = (0..7)
+            .map(|nonce| Transaction::new(vec![1; 32], nonce, Payload::Data(vec![])))
+            .collect();
+        for count in 1..=txs.len() {
+            let root = merkle_root(&txs[..count]);
+            for index in 0..count {
+                let proof = merkle_proof(&txs[..count], index).unwrap();
+                assert!(proof.verify(&txs[index], &root));
+                // The proof is bound to the transaction and to its position
+                assert!(!proof.verify(&txs[(index + 1) % txs.len()], &root));
+                let moved = MerkleProof { index: (index as u64 + 1) % count as u64, ..proof.clone() };
+                assert!(count == 1 || !moved.verify(&txs[index], &root));
+            }
+            assert_eq!(merkle_proof(&txs[..count], count), None);
+        }
+
+        let proof = merkle_proof(&txs, 6).unwrap();
+        let truncated = MerkleProof { path: proof.path[1..].to_vec(), ..proof.clone() };
+        assert_eq!(truncated.root(&txs[6]), None);
+        let out_of_bounds = MerkleProof { index: 7, ..proof };
+        assert_eq!(out_of_bounds.root(&txs[6]), None);
+    }
+
+    #[test]
+    fn test_proof_of_work() {
+        let hex = |hash| BlockHash(hash).to_string();
+        assert_eq!(
+            hex(SingleSha256.hash(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex(DoubleSha256.hash(b"abc")),
+            "4f8b42c22dd3729b519ba6f68d2da7cc5b2d606d05daed5ad5128cc03e6c6358"
+        );
+        assert_eq!(
+            hex(Blake3.hash(b"abc")),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+        assert_eq!(
+            hex(Scrypt.hash(b"abc")),
+            "e652c1c3b7a8cd99d2edc49d4509f545c80e4395765e7225c4dde5d80dd76519"
+        );
+
+        // The block is still identified by its SHA-256 hash
+        let mut block = Block::new(vec![], "test".to_string(), 0, DanceMove::Y);
+        let mut rng = StdRng::seed_from_u64(42);
+        let target = Target::from_leading_zeros(4);
+        let hash = block.solve_block(&mut rng, &Scrypt, &target, None).unwrap();
+        assert_eq!(hash, block.pow_hash(&Scrypt).to_vec());
+        assert_ne!(block.pow_hash(&Scrypt), block.hash_block());
+        assert_eq!(block.pow_hash(&SingleSha256), block.hash_block());
+    }
+
     #[test]
     fn test_new_genesis() {
         let mut genesis = Block::new(Vec::new(), "Genesis".to_string(), 42, DanceMove::C);
         let mut rng = StdRng::seed_from_u64(42);
         genesis.nonce = rng.random();
-        genesis.solve_block(&mut rng, 10, None).unwrap();
+        genesis.solve_block(&mut rng, &SingleSha256, &Target::from_leading_zeros(10), None).unwrap();
         assert!(genesis.is_genesis(10));
     }
 }
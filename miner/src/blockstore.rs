@@ -0,0 +1,272 @@
+use crate::block::{Block, BlockHash, BlockIdHasher};
+use std::collections::HashMap;
+
+/// A block held by a [`BlockStore`], together with its cached hash and
+/// the links to its parent and children.
+#[derive(Debug, Clone)]
+pub struct BlockNode {
+    block: Block,
+    hash: BlockHash,
+    parent: Option<BlockHash>,
+    children: Vec<BlockHash>,
+    height: u64,
+    work: u128,
+    chainwork: u128,
+}
+
+impl BlockNode {
+    /// Get the stored block
+    pub fn block(&self) -> &Block {
+        &self.block
+    }
+
+    /// Get the (cached) hash of the stored block
+    pub fn hash(&self) -> &BlockHash {
+        &self.hash
+    }
+
+    /// Get the hash of the parent block, `None` for the genesis block
+    pub fn parent(&self) -> Option<&BlockHash> {
+        self.parent.as_ref()
+    }
+
+    /// Get the hashes of the children blocks, in insertion order
+    pub fn children(&self) -> &[BlockHash] {
+        &self.children
+    }
+
+    /// Distance to the genesis block (which has height 0)
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    /// Expected number of hashes needed to mine this block
+    pub fn work(&self) -> u128 {
+        self.work
+    }
+
+    /// Sum of the work of this block and of all its ancestors
+    pub fn chainwork(&self) -> u128 {
+        self.chainwork
+    }
+}
+
+/// Reasons for which a block could not be inserted in a [`BlockStore`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InsertError {
+    /// A block with the same hash is already stored.
+    Duplicate,
+    /// The parent of the block is not stored (yet). The block is handed
+    /// back so the caller can keep it around.
+    UnknownParent(Box<Block>),
+}
+
+/// Blocks indexed by their hash. Parent lookup and insertion are O(1)
+/// amortized.
+#[derive(Debug, Clone)]
+pub struct BlockStore {
+    nodes: HashMap<BlockHash, BlockNode, BlockIdHasher>,
+    root: BlockHash,
+}
+
+impl BlockStore {
+    /// Creates a store containing only the genesis block. The genesis
+    /// block is common to all the branches, its work is 0.
+    pub fn new(genesis: Block) -> Self {
+        let hash = genesis.block_hash();
+        let node = BlockNode {
+            block: genesis,
+            hash,
+            parent: None,
+            children: Vec::new(),
+            height: 0,
+            work: 0,
+            chainwork: 0,
+        };
+
+        let mut nodes = HashMap::default();
+        nodes.insert(hash, node);
+
+        BlockStore { nodes, root: hash }
+    }
+
+    /// Inserts a block below its parent and returns its hash. `work` is
+    /// the expected number of hashes needed to mine the block.
+    pub fn insert(&mut self, block: Block, work: u128) -> Result<BlockHash, InsertError> {
+        let hash = block.block_hash();
+        if self.nodes.contains_key(&hash) {
+            return Err(InsertError::Duplicate);
+        }
+
+        let Some(parent) = block.parent().and_then(|parent| self.nodes.get_mut(&parent)) else {
+            return Err(InsertError::UnknownParent(Box::new(block)));
+        };
+        parent.children.push(hash);
+        let node = BlockNode {
+            block,
+            hash,
+            parent: Some(parent.hash),
+            children: Vec::new(),
+            height: parent.height + 1,
+            work,
+            chainwork: parent.chainwork.saturating_add(work),
+        };
+
+        self.nodes.insert(hash, node);
+        Ok(hash)
+    }
+
+    /// Look for a block by its hash
+    pub fn get(&self, hash: &BlockHash) -> Option<&BlockNode> {
+        self.nodes.get(hash)
+    }
+
+    /// Returns true if a block with this hash is stored
+    pub fn contains(&self, hash: &BlockHash) -> bool {
+        self.nodes.contains_key(hash)
+    }
+
+    /// Get the genesis block
+    pub fn root(&self) -> &BlockNode {
+        &self.nodes[&self.root]
+    }
+
+    /// Get the parent of a stored block
+    pub fn parent(&self, node: &BlockNode) -> Option<&BlockNode> {
+        node.parent.map(|hash| &self.nodes[&hash])
+    }
+
+    /// Iterate from a stored block up to the genesis block, both included
+    pub fn ancestors<'a>(&'a self, node: &'a BlockNode) -> Ancestors<'a> {
+        Ancestors {
+            store: self,
+            next: Some(node),
+        }
+    }
+
+    /// Iterate over all stored blocks, parents before their children
+    pub fn iter(&self) -> impl Iterator<Item = &BlockNode> {
+        let mut to_visit = vec![self.root()];
+        std::iter::from_fn(move || {
+            let node = to_visit.pop()?;
+            to_visit.extend(node.children.iter().rev().map(|hash| &self.nodes[hash]));
+            Some(node)
+        })
+    }
+
+    /// Iterate over the children of a stored block, in insertion order
+    pub fn children<'a>(&'a self, node: &'a BlockNode) -> impl Iterator<Item = &'a BlockNode> + 'a {
+        node.children.iter().map(|hash| &self.nodes[hash])
+    }
+
+    /// Number of stored blocks, genesis included
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// A store always holds at least its genesis block
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+}
+
+/// Iterator over the ancestors of a block, see [`BlockStore::ancestors`].
+pub struct Ancestors<'a> {
+    store: &'a BlockStore,
+    next: Option<&'a BlockNode>,
+}
+
+impl<'a> Iterator for Ancestors<'a> {
+    type Item = &'a BlockNode;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.next?;
+        self.next = self.store.parent(node);
+        Some(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::DanceMove;
+
+    fn create_test_block(parent_hash: &[u8], nonce: u64, miner: &str) -> Block {
+        Block::new(parent_hash.to_vec(), miner.to_string(), nonce, DanceMove::Y)
+    }
+
+    fn child_of(parent: &BlockHash, nonce: u64, miner: &str) -> Block {
+        create_test_block(parent.as_bytes(), nonce, miner)
+    }
+
+    #[test]
+    fn test_insert_and_lookup() {
+        let genesis = create_test_block(&[], 0, "Genesis");
+        let genesis_hash = genesis.block_hash();
+        let mut store = BlockStore::new(genesis);
+
+        let block1 = child_of(&genesis_hash, 42, "miner1");
+        let block1_hash = store.insert(block1.clone(), 1).unwrap();
+        assert_eq!(block1_hash, block1.block_hash());
+
+        let node = store.get(&block1_hash).unwrap();
+        assert_eq!(node.block(), &block1);
+        assert_eq!(node.height(), 1);
+        assert_eq!(store.parent(node).unwrap().hash(), &genesis_hash);
+        assert_eq!(store.children(store.root()).count(), 1);
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    fn test_traversal() {
+        let genesis = create_test_block(&[], 0, "Genesis");
+        let genesis_hash = genesis.block_hash();
+        let mut store = BlockStore::new(genesis);
+
+        let block1 = store.insert(child_of(&genesis_hash, 42, "miner1"), 1).unwrap();
+        let block2 = store.insert(child_of(&genesis_hash, 43, "miner2"), 1).unwrap();
+        let block3 = store.insert(child_of(&block1, 44, "miner3"), 1).unwrap();
+
+        let ancestors: Vec<_> = store
+            .ancestors(store.get(&block3).unwrap())
+            .map(|n| *n.hash())
+            .collect();
+        assert_eq!(ancestors, vec![block3, block1, genesis_hash]);
+
+        let all: Vec<_> = store.iter().map(|n| *n.hash()).collect();
+        assert_eq!(all, vec![genesis_hash, block1, block3, block2]);
+    }
+
+    #[test]
+    fn test_chainwork() {
+        let genesis = create_test_block(&[], 0, "Genesis");
+        let genesis_hash = genesis.block_hash();
+        let mut store = BlockStore::new(genesis);
+
+        let block1 = store.insert(child_of(&genesis_hash, 42, "miner1"), 4).unwrap();
+        let block2 = store.insert(child_of(&block1, 43, "miner2"), 8).unwrap();
+
+        assert_eq!(store.root().chainwork(), 0);
+        assert_eq!(store.get(&block1).unwrap().chainwork(), 4);
+        assert_eq!(store.get(&block2).unwrap().work(), 8);
+        assert_eq!(store.get(&block2).unwrap().chainwork(), 12);
+    }
+
+    #[test]
+    fn test_insert_errors() {
+        let genesis = create_test_block(&[], 0, "Genesis");
+        let genesis_hash = genesis.block_hash();
+        let mut store = BlockStore::new(genesis);
+
+        let block1 = child_of(&genesis_hash, 42, "miner1");
+        store.insert(block1.clone(), 1).unwrap();
+        assert_eq!(store.insert(block1, 1), Err(InsertError::Duplicate));
+
+        let orphan = create_test_block(&[0xFF; 32], 10, "miner2");
+        assert_eq!(
+            store.insert(orphan.clone(), 1),
+            Err(InsertError::UnknownParent(Box::new(orphan)))
+        );
+        assert_eq!(store.len(), 2);
+    }
+}
@@ -0,0 +1,1263 @@
+use crate::block::Block;
+use crate::block::BlockHash;
+use crate::block::BlockHashSet;
+use crate::block::BlockIdHasher;
+use crate::block::{merkle_root, timestamp_now, BLOCK_VERSION};
+use crate::block::{MAX_BLOCK_TRANSACTIONS, MAX_MINER_NAME_LEN};
+use crate::blockstore::{Ancestors, BlockNode, BlockStore};
+use crate::forkchoice::{ForkChoice, HeaviestWork};
+use crate::encoding::to_hex;
+use crate::ledger::{Balance, Ledger};
+use crate::params::ChainParams;
+use crate::transaction::{InvalidTransaction, TxId};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+/// Number of blocks whose median timestamp a new block must be after.
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// How far in the future, in milliseconds, a block timestamp can be.
+pub const MAX_FUTURE_DRIFT: u64 = 2 * 60 * 1000;
+
+/// Largest number of blocks waiting for their parent. The oldest ones are
+/// dropped beyond it.
+pub const MAX_ORPHANS: usize = 1000;
+
+/// A tree of blocks rooted at a genesis block, along with the blocks
+/// still waiting for their parent.
+#[derive(Debug)]
+pub struct Blockchain {
+    /// The blockchain is represented as a store of blocks indexed
+    /// by their hash.
+    blocks: BlockStore,
+    /// Blocks whose parent is not known yet, indexed by parent hash.
+    orphans: HashMap<BlockHash, Vec<Block>, BlockIdHasher>,
+    /// Hashes of the blocks in `orphans`.
+    orphan_hashes: BlockHashSet,
+    /// Hashes and parent hashes of the blocks in `orphans`, oldest first.
+    orphan_order: VecDeque<(BlockHash, BlockHash)>,
+    /// Rule choosing the tip among the blocks of the chain.
+    fork_choice: Box<dyn ForkChoice>,
+    /// Cached result of `fork_choice`.
+    tip: BlockHash,
+    /// Consensus parameters, including the difficulty retargeting ones.
+    params: ChainParams,
+    /// Accounts after the block `ledger_tip`, the tip unless a block is
+    /// being added.
+    ledger: Ledger,
+    ledger_tip: BlockHash,
+    /// Hashes of the blocks of `blocks`, in insertion order.
+    inserted: Vec<BlockHash>,
+}
+
+/// Why a block can't be part of the blockchain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidBlock {
+    /// The block has no parent but the chain already has a genesis block.
+    SecondGenesis,
+    /// The parent hash is neither empty nor a 32 bytes hash.
+    MalformedParentHash,
+    /// The miner name is longer than [`MAX_MINER_NAME_LEN`] bytes.
+    MinerNameTooLong,
+    /// The block format version isn't [`BLOCK_VERSION`].
+    UnsupportedVersion,
+    /// The bits aren't a valid compact target.
+    MalformedTarget,
+    /// The height isn't the height of the parent plus one.
+    WrongHeight,
+    /// The timestamp isn't after the median timestamp of the last
+    /// [`MEDIAN_TIME_SPAN`] blocks.
+    TimestampTooOld,
+    /// The timestamp is more than [`MAX_FUTURE_DRIFT`] milliseconds ahead.
+    TimestampTooFarInFuture,
+    /// The target isn't the one required by the chain.
+    WrongDifficulty,
+    /// The public key isn't a valid ed25519 public key.
+    MalformedPublicKey,
+    /// The header isn't signed by the public key of the block.
+    InvalidSignature,
+    /// The block has more than [`MAX_BLOCK_TRANSACTIONS`] transactions.
+    TooManyTransactions,
+    /// The Merkle root isn't the root of the transactions.
+    MerkleRootMismatch,
+    /// A transaction of the block is invalid.
+    InvalidTransaction(InvalidTransaction),
+    /// The block reward would overflow the balance of the miner.
+    BalanceOverflow,
+}
+
+impl InvalidBlock {
+    /// Short machine readable identifier of the reason
+    pub fn code(&self) -> &'static str {
+        match self {
+            InvalidBlock::SecondGenesis => "second_genesis",
+            InvalidBlock::MalformedParentHash => "malformed_parent_hash",
+            InvalidBlock::MinerNameTooLong => "miner_name_too_long",
+            InvalidBlock::UnsupportedVersion => "unsupported_version",
+            InvalidBlock::MalformedTarget => "malformed_target",
+            InvalidBlock::WrongHeight => "wrong_height",
+            InvalidBlock::TimestampTooOld => "timestamp_too_old",
+            InvalidBlock::TimestampTooFarInFuture => "timestamp_too_far_in_future",
+            InvalidBlock::WrongDifficulty => "wrong_difficulty",
+            InvalidBlock::MalformedPublicKey => "malformed_public_key",
+            InvalidBlock::InvalidSignature => "invalid_signature",
+            InvalidBlock::TooManyTransactions => "too_many_transactions",
+            InvalidBlock::MerkleRootMismatch => "merkle_root_mismatch",
+            InvalidBlock::InvalidTransaction(_) => "invalid_transaction",
+            InvalidBlock::BalanceOverflow => "balance_overflow",
+        }
+    }
+}
+
+impl fmt::Display for InvalidBlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidBlock::SecondGenesis => write!(f, "the chain already has a genesis block"),
+            InvalidBlock::MalformedParentHash => {
+                write!(f, "the parent hash must be empty or 32 bytes long")
+            }
+            InvalidBlock::MinerNameTooLong => write!(
+                f,
+                "the miner name is longer than {} bytes",
+                MAX_MINER_NAME_LEN
+            ),
+            InvalidBlock::UnsupportedVersion => {
+                write!(f, "the block version must be {}", BLOCK_VERSION)
+            }
+            InvalidBlock::MalformedTarget => write!(f, "the bits aren't a valid compact target"),
+            InvalidBlock::WrongHeight => write!(f, "the height must be the parent height plus one"),
+            InvalidBlock::TimestampTooOld => write!(
+                f,
+                "the timestamp must be after the median of the last {} blocks",
+                MEDIAN_TIME_SPAN
+            ),
+            InvalidBlock::TimestampTooFarInFuture => write!(
+                f,
+                "the timestamp is more than {}ms in the future",
+                MAX_FUTURE_DRIFT
+            ),
+            InvalidBlock::WrongDifficulty => write!(f, "the target isn't the required one"),
+            InvalidBlock::MalformedPublicKey => {
+                write!(f, "the public key isn't a valid ed25519 public key")
+            }
+            InvalidBlock::InvalidSignature => {
+                write!(f, "the block isn't signed by its public key")
+            }
+            InvalidBlock::TooManyTransactions => write!(
+                f,
+                "the block has more than {} transactions",
+                MAX_BLOCK_TRANSACTIONS
+            ),
+            InvalidBlock::MerkleRootMismatch => {
+                write!(f, "the Merkle root doesn't match the transactions")
+            }
+            InvalidBlock::InvalidTransaction(reason) => write!(f, "invalid transaction: {}", reason),
+            InvalidBlock::BalanceOverflow => {
+                write!(f, "the block reward would overflow the balance of the miner")
+            }
+        }
+    }
+}
+
+/// Checks the fields of a block that don't depend on the rest of the chain.
+/// Like the proof of work, the signature is checked apart, see
+/// [`Block::verify_signature`].
+pub fn check_structure(block: &Block) -> Result<(), InvalidBlock> {
+    check_header(block)?;
+    if block.transactions.len() > MAX_BLOCK_TRANSACTIONS {
+        return Err(InvalidBlock::TooManyTransactions);
+    }
+    for transaction in &block.transactions {
+        transaction.check_structure().map_err(InvalidBlock::InvalidTransaction)?;
+    }
+    if block.merkle_root != merkle_root(&block.transactions) {
+        return Err(InvalidBlock::MerkleRootMismatch);
+    }
+    Ok(())
+}
+
+/// Checks the fields of the header of a block that don't depend on the
+/// rest of the chain, ignoring the transactions
+pub fn check_header(block: &Block) -> Result<(), InvalidBlock> {
+    if block.version != BLOCK_VERSION {
+        return Err(InvalidBlock::UnsupportedVersion);
+    }
+    if block.target().is_none() {
+        return Err(InvalidBlock::MalformedTarget);
+    }
+    if !block.parent_hash.is_empty() && block.parent_hash.len() != 32 {
+        return Err(InvalidBlock::MalformedParentHash);
+    }
+    if block.miner.len() > MAX_MINER_NAME_LEN {
+        return Err(InvalidBlock::MinerNameTooLong);
+    }
+    Ok(())
+}
+
+/// What happened to a block given to [`Blockchain::add_block`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddBlockOutcome {
+    /// The block, and the orphans that were waiting for it, joined the chain.
+    Inserted { tip_changed: bool },
+    /// The parent is unknown, the block waits in the orphan pool.
+    Orphaned,
+    /// The block is already in the chain or in the orphan pool.
+    Duplicate,
+    /// The block was dropped.
+    Invalid(InvalidBlock),
+}
+
+impl Blockchain {
+    /// Creates a new Blockchain containing only the genesis block. The
+    /// tip is the block with the most accumulated work.
+    pub fn new(genesis: Block) -> Self {
+        let tip = genesis.block_hash();
+        let params = ChainParams::default();
+        Blockchain {
+            ledger: Ledger::genesis(&genesis, params.block_reward),
+            ledger_tip: tip,
+            blocks: BlockStore::new(genesis),
+            orphans: HashMap::default(),
+            orphan_hashes: BlockHashSet::default(),
+            orphan_order: VecDeque::new(),
+            fork_choice: Box::new(HeaviestWork),
+            tip,
+            params,
+            inserted: vec![tip],
+        }
+    }
+
+    /// Chooses the tip with another rule
+    pub fn with_fork_choice(mut self, fork_choice: Box<dyn ForkChoice>) -> Self {
+        self.tip = fork_choice.best_tip(&self.blocks);
+        self.fork_choice = fork_choice;
+        self.update_ledger();
+        self
+    }
+
+    /// Uses other consensus parameters. The blocks are added again, since
+    /// the rewards and the difficulties depend on the parameters: the ones
+    /// that are no longer valid are dropped.
+    pub fn with_params(mut self, params: ChainParams) -> Self {
+        let mut blocks: Vec<Block> = self.inserted_since(1).map(|node| node.block().clone()).collect();
+        blocks.extend(std::mem::take(&mut self.orphans).into_values().flatten());
+        self.params = params;
+        self.reset(self.genesis().block().clone());
+        for block in blocks {
+            self.add_block(block);
+        }
+        self
+    }
+
+    /// Starts over from another genesis block, keeping the fork choice
+    /// rule and the parameters
+    pub fn reset(&mut self, genesis: Block) {
+        self.tip = genesis.block_hash();
+        self.ledger = Ledger::genesis(&genesis, self.params.block_reward);
+        self.ledger_tip = self.tip;
+        self.blocks = BlockStore::new(genesis);
+        self.orphans.clear();
+        self.orphan_hashes.clear();
+        self.orphan_order.clear();
+        self.inserted = vec![self.tip];
+    }
+
+    /// Creates a new Blockchain from the provided genesis
+    /// block and vector of valid blocks.
+    pub fn new_from_genesis_and_vec(
+        genesis: Block,
+        blocks: Vec<Block>,
+    ) -> (Self, Vec<Block>) {
+        let mut chain = Blockchain::new(genesis);
+        let mut invalid_blocks = vec![];
+
+        for block in blocks {
+            if let AddBlockOutcome::Invalid(_) = chain.add_block(block.clone()) {
+                invalid_blocks.push(block);
+            }
+        }
+
+        let mut remaining_blocks: Vec<Block> =
+            std::mem::take(&mut chain.orphans).into_values().flatten().collect();
+        chain.orphan_hashes.clear();
+        chain.orphan_order.clear();
+        remaining_blocks.extend(invalid_blocks);
+
+        (chain, remaining_blocks)
+    }
+
+    /// Checks a block the way [`Blockchain::add_block`] does, without
+    /// adding it. Fails with the outcome of `add_block` if the block would
+    /// be dropped, as a duplicate or as an invalid block.
+    pub fn check_block(&self, block: &Block) -> Result<(), AddBlockOutcome> {
+        self.validate(block).map(|_| ())
+    }
+
+    /// Checks a block, and returns the accounts after it, or `None` if it
+    /// is an orphan
+    fn validate(&self, block: &Block) -> Result<Option<Ledger>, AddBlockOutcome> {
+        check_structure(block).map_err(AddBlockOutcome::Invalid)?;
+        let hash = block.block_hash();
+        if self.blocks.contains(&hash) || self.orphan_hashes.contains(&hash) {
+            return Err(AddBlockOutcome::Duplicate);
+        }
+        let Some(parent) = block.parent() else {
+            return Err(AddBlockOutcome::Invalid(InvalidBlock::SecondGenesis));
+        };
+        let Some(parent) = self.blocks.get(&parent) else {
+            return Ok(None);
+        };
+        let ledger = self
+            .check_context(block, parent)
+            .and_then(|()| self.ledger_at(parent))
+            .and_then(|ledger| self.apply(ledger, block))
+            .map_err(AddBlockOutcome::Invalid)?;
+        Ok(Some(ledger))
+    }
+
+    /// Adds a block to the chain, or to the orphan pool if its parent is
+    /// unknown. Orphans waiting for the block are added along with it.
+    pub fn add_block(&mut self, block: Block) -> AddBlockOutcome {
+        let ledger = match self.validate(&block) {
+            Ok(Some(ledger)) => ledger,
+            Ok(None) => {
+                if let Some(parent) = block.parent() {
+                    self.add_orphan(block.block_hash(), parent, block);
+                }
+                return AddBlockOutcome::Orphaned;
+            }
+            Err(outcome) => return outcome,
+        };
+
+        let old_tip = self.tip;
+        let orphan_count = self.orphan_hashes.len();
+        // Each block comes with the accounts after it, so that its orphans
+        // are checked without going through the chain again
+        let mut to_insert = vec![(block, ledger)];
+
+        while let Some((block, ledger)) = to_insert.pop() {
+            let work = block.target().map_or(0, |target| target.work());
+            let Ok(hash) = self.blocks.insert(block, work) else {
+                continue;
+            };
+            self.inserted.push(hash);
+            self.tip = self.fork_choice.update(&self.blocks, &self.tip, &hash);
+
+            // Adopt the valid orphans that were waiting for this block
+            let node = self.blocks.get(&hash).unwrap();
+            for orphan in self.orphans.remove(&hash).unwrap_or_default() {
+                self.orphan_hashes.remove(&orphan.block_hash());
+                let checked = self
+                    .check_context(&orphan, node)
+                    .and_then(|()| self.apply(ledger.clone(), &orphan));
+                if let Ok(orphan_ledger) = checked {
+                    to_insert.push((orphan, orphan_ledger));
+                }
+            }
+        }
+
+        if self.orphan_hashes.len() != orphan_count {
+            let orphan_hashes = &self.orphan_hashes;
+            self.orphan_order.retain(|(hash, _)| orphan_hashes.contains(hash));
+        }
+        self.update_ledger();
+        AddBlockOutcome::Inserted {
+            tip_changed: self.tip != old_tip,
+        }
+    }
+
+    /// Puts a block in the orphan pool, dropping the oldest orphan if the
+    /// pool is full
+    fn add_orphan(&mut self, hash: BlockHash, parent: BlockHash, block: Block) {
+        if self.orphan_hashes.len() == MAX_ORPHANS {
+            if let Some((oldest, oldest_parent)) = self.orphan_order.pop_front() {
+                self.orphan_hashes.remove(&oldest);
+                if let Some(siblings) = self.orphans.get_mut(&oldest_parent) {
+                    siblings.retain(|orphan| orphan.block_hash() != oldest);
+                    if siblings.is_empty() {
+                        self.orphans.remove(&oldest_parent);
+                    }
+                }
+            }
+        }
+        self.orphan_hashes.insert(hash);
+        self.orphan_order.push_back((hash, parent));
+        self.orphans.entry(parent).or_default().push(block);
+    }
+
+    /// Accounts after the tip
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    /// Accounts after the tip with their alias, richest first
+    pub fn balances(&self) -> Vec<Balance> {
+        let mut aliases: HashMap<&[u8], &str> = HashMap::new();
+        for node in self.best_chain() {
+            let block = node.block();
+            if !block.miner.is_empty() {
+                aliases.entry(&block.public_key).or_insert(&block.miner);
+            }
+        }
+
+        let mut balances: Vec<Balance> = self
+            .ledger
+            .accounts()
+            .map(|(key, account)| Balance {
+                public_key: to_hex(key),
+                miner: aliases.get(key).copied().unwrap_or_default().to_string(),
+                balance: account.balance,
+                nonce: account.nonce,
+            })
+            .collect();
+        balances.sort_by(|a, b| b.balance.cmp(&a.balance).then_with(|| a.public_key.cmp(&b.public_key)));
+        balances
+    }
+
+    /// Accounts after `node`. They are computed from the accounts after the
+    /// tip, undoing the blocks down to the common ancestor of the tip and
+    /// `node`, then applying the blocks up to `node`.
+    pub fn ledger_at(&self, node: &BlockNode) -> Result<Ledger, InvalidBlock> {
+        let mut ledger = self.ledger.clone();
+        let reward = self.params.block_reward;
+        let from = self.blocks.get(&self.ledger_tip).unwrap();
+        let fork = *self.common_ancestor(from, node).hash();
+
+        for undone in self.ancestors(from).take_while(|n| *n.hash() != fork) {
+            ledger.undo_block(undone.block(), reward);
+        }
+        let redone: Vec<&BlockNode> = self.ancestors(node).take_while(|n| *n.hash() != fork).collect();
+        for block in redone.iter().rev() {
+            ledger = self.apply(ledger, block.block())?;
+        }
+        Ok(ledger)
+    }
+
+    /// Applies a block to the accounts after its parent
+    fn apply(&self, mut ledger: Ledger, block: &Block) -> Result<Ledger, InvalidBlock> {
+        ledger.apply_block(block, self.params.block_reward)?;
+        Ok(ledger)
+    }
+
+    /// Moves the ledger to the tip, after a change of tip. The blocks of the
+    /// chain were all checked with the current parameters, so this only
+    /// fails, keeping the ledger of the previous tip, if they are broken.
+    fn update_ledger(&mut self) {
+        if self.ledger_tip != self.tip {
+            if let Ok(ledger) = self.ledger_at(self.tip()) {
+                self.ledger = ledger;
+                self.ledger_tip = self.tip;
+            }
+        }
+    }
+
+    /// Latest block that is an ancestor of both `a` and `b`
+    pub fn common_ancestor<'a>(&'a self, mut a: &'a BlockNode, mut b: &'a BlockNode) -> &'a BlockNode {
+        while a.height() > b.height() {
+            a = self.blocks.parent(a).unwrap();
+        }
+        while b.height() > a.height() {
+            b = self.blocks.parent(b).unwrap();
+        }
+        while a.hash() != b.hash() {
+            a = self.blocks.parent(a).unwrap();
+            b = self.blocks.parent(b).unwrap();
+        }
+        a
+    }
+
+    /// Checks the fields of a block that depend on its parent, but not its
+    /// transactions
+    fn check_context(&self, block: &Block, parent: &BlockNode) -> Result<(), InvalidBlock> {
+        if block.height != parent.height() + 1 {
+            return Err(InvalidBlock::WrongHeight);
+        }
+        if block.timestamp <= self.median_time_past(parent) {
+            return Err(InvalidBlock::TimestampTooOld);
+        }
+        if block.timestamp > timestamp_now().saturating_add(MAX_FUTURE_DRIFT) {
+            return Err(InvalidBlock::TimestampTooFarInFuture);
+        }
+        if block.bits != self.next_bits(parent) {
+            return Err(InvalidBlock::WrongDifficulty);
+        }
+        Ok(())
+    }
+
+    /// Median timestamp of the last [`MEDIAN_TIME_SPAN`] blocks ending at
+    /// `node`. A child of `node` must have a greater timestamp.
+    pub fn median_time_past(&self, node: &BlockNode) -> u64 {
+        let mut timestamps: Vec<u64> = self
+            .ancestors(node)
+            .take(MEDIAN_TIME_SPAN)
+            .map(|ancestor| ancestor.block().timestamp)
+            .collect();
+        timestamps.sort_unstable();
+        timestamps[timestamps.len() / 2]
+    }
+
+    /// Get the consensus parameters of the chain
+    pub fn params(&self) -> &ChainParams {
+        &self.params
+    }
+
+    /// Compact target a child of `parent` must have. It only changes at
+    /// the start of a retarget window, depending on how long the blocks of
+    /// the previous window took to mine.
+    pub fn next_bits(&self, parent: &BlockNode) -> u32 {
+        if parent.parent().is_none() {
+            return self.params.initial_bits();
+        }
+        let bits = parent.block().bits;
+        if !self.params.is_retarget_height(parent.height() + 1) {
+            return bits;
+        }
+
+        // The first window starts at the genesis block
+        let window = usize::try_from(self.params.retarget_window).unwrap_or(usize::MAX);
+        let first = self.ancestors(parent).take(window.saturating_add(1)).last().unwrap();
+        let elapsed = parent.block().timestamp.saturating_sub(first.block().timestamp);
+        self.params.retarget(bits, parent.height() - first.height(), elapsed)
+    }
+
+    /// Get the block new blocks should be mined on, according to the
+    /// fork choice rule
+    pub fn tip(&self) -> &BlockNode {
+        self.blocks.get(&self.tip).unwrap()
+    }
+
+    /// Get the genesis block
+    pub fn genesis(&self) -> &BlockNode {
+        self.blocks.root()
+    }
+
+    /// Look for a block of the chain by its hash
+    pub fn get(&self, hash: &BlockHash) -> Option<&BlockNode> {
+        self.blocks.get(hash)
+    }
+
+    /// Returns true if the block is part of the chain (orphans are not)
+    pub fn contains(&self, hash: &BlockHash) -> bool {
+        self.blocks.contains(hash)
+    }
+
+    /// Iterate over the children of a block of the chain
+    pub fn children<'a>(&'a self, node: &'a BlockNode) -> impl Iterator<Item = &'a BlockNode> + 'a {
+        self.blocks.children(node)
+    }
+
+    /// Iterate from a block up to the genesis block
+    pub fn ancestors<'a>(&'a self, node: &'a BlockNode) -> Ancestors<'a> {
+        self.blocks.ancestors(node)
+    }
+
+    /// Iterate over the best chain, from the tip down to the genesis block
+    pub fn best_chain(&self) -> Ancestors<'_> {
+        self.blocks.ancestors(self.tip())
+    }
+
+    /// Block of the best chain holding a transaction, with the position of
+    /// the transaction in it. The newest block is returned if there are
+    /// several.
+    pub fn find_transaction(&self, txid: &TxId) -> Option<(&BlockNode, usize)> {
+        self.best_chain().find_map(|node| {
+            let index = node.block().transactions.iter().position(|tx| tx.txid() == *txid)?;
+            Some((node, index))
+        })
+    }
+
+    /// Returns true if the block is an ancestor of the tip (or the tip itself)
+    pub fn is_on_best_chain(&self, hash: &BlockHash) -> bool {
+        let Some(node) = self.blocks.get(hash) else {
+            return false;
+        };
+        let tip = self.tip();
+        node.height() <= tip.height()
+            && self
+                .ancestors(tip)
+                .nth((tip.height() - node.height()) as usize)
+                .is_some_and(|ancestor| ancestor.hash() == node.hash())
+    }
+
+    /// Number of blocks inserted so far, genesis included: the cursor of
+    /// the next block inserted, see [`Blockchain::inserted_since`]
+    pub fn cursor(&self) -> usize {
+        self.inserted.len()
+    }
+
+    /// Iterate over the blocks inserted after the first `cursor` ones, in
+    /// insertion order, so parents before children. A block waiting in
+    /// the orphan pool only gets a cursor once inserted.
+    pub fn inserted_since(&self, cursor: usize) -> impl Iterator<Item = &BlockNode> {
+        self.inserted
+            .iter()
+            .skip(cursor)
+            .map(|hash| self.blocks.get(hash).unwrap())
+    }
+
+    /// Iterate over all the blocks of the chain, parents before children
+    pub fn iter(&self) -> impl Iterator<Item = &BlockNode> {
+        self.blocks.iter()
+    }
+
+    /// Get the underlying block store
+    pub fn store(&self) -> &BlockStore {
+        &self.blocks
+    }
+
+    /// Number of blocks in the chain, genesis included
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// A chain always holds at least its genesis block
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Number of blocks waiting for their parent
+    pub fn orphan_count(&self) -> usize {
+        self.orphan_hashes.len()
+    }
+
+    fn print_tree(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        node: &BlockNode,
+        prefixes: &mut Vec<bool>,
+    ) -> fmt::Result {
+        // Print the current node
+        if !prefixes.is_empty() {
+            // Print connecting lines from parent
+            for &is_last in &prefixes[..prefixes.len() - 1] {
+                write!(f, "{}", if is_last { "    " } else { "│   " })?;
+            }
+
+            // Print the appropriate connector
+            let is_last = *prefixes.last().unwrap();
+            write!(f, "{}", if is_last { "└── " } else { "├── " })?;
+        }
+
+        // Print the block info
+        let block = node.block();
+        writeln!(f, "{} (nonce: {})", block.author(), block.nonce)?;
+
+        // Recursively print children
+        let child_count = node.children().len();
+        for (i, child) in self.blocks.children(node).enumerate() {
+            prefixes.push(i == child_count - 1); // true if this is the last child
+            self.print_tree(f, child, prefixes)?;
+            prefixes.pop();
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Blockchain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.print_tree(f, self.blocks.root(), &mut Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{DanceMove, DIFFICULTY};
+    use crate::target::Target;
+    use crate::forkchoice::LongestChain;
+    use crate::ledger::Account;
+    use crate::transaction::{Payload, Transaction};
+
+    fn create_genesis(nonce_init: u64) -> Block {
+        Block::new(vec![], "Genesis".to_string(), nonce_init, DanceMove::Y)
+    }
+
+    fn create_test_block(parent: &Block, nonce_init: u64, miner: &str) -> Block {
+        let mut block = Block::new_child(
+            parent,
+            miner.to_string(),
+            DanceMove::Y,
+            parent.timestamp + 1,
+            ChainParams::default().initial_bits(),
+        );
+        block.nonce = nonce_init;
+        block
+    }
+
+    #[test]
+    fn test_empty_blocks() {
+        let genesis = create_genesis(0);
+        let (blockchain, _) =
+            Blockchain::new_from_genesis_and_vec(genesis, vec![]);
+
+        let store = &blockchain.blocks;
+        assert_eq!(store.children(store.root()).count(), 0);
+    }
+
+    #[test]
+    fn test_single_valid_block() {
+        let genesis = create_genesis(0);
+
+        let block1 = create_test_block(&genesis, 42, "miner1");
+        let (blockchain, _) =
+            Blockchain::new_from_genesis_and_vec(genesis, vec![block1]);
+
+        let store = &blockchain.blocks;
+        let root = store.root();
+        assert_eq!(store.children(root).count(), 1);
+        assert_eq!(store.children(root).next().unwrap().block().miner, "miner1");
+    }
+
+    #[test]
+    fn test_multiple_levels() {
+        let genesis = create_genesis(0);
+
+        let block1 = create_test_block(&genesis, 42, "miner1");
+        let block2 = create_test_block(&genesis, 43, "miner2");
+        let block3 = create_test_block(&block1, 44, "miner3");
+
+        let (blockchain, remaining) = Blockchain::new_from_genesis_and_vec(
+            genesis,
+            vec![block1, block2, block3],
+        );
+
+        let store = &blockchain.blocks;
+        let root = store.root();
+        assert_eq!(store.children(root).count(), 2); // block1 and block2
+
+        // Find block1 in children
+        let block1_node = store
+            .children(root)
+            .find(|n| n.block().miner == "miner1")
+            .unwrap();
+
+        assert_eq!(store.children(block1_node).count(), 1); // block3
+        assert_eq!(store.children(block1_node).next().unwrap().block().miner, "miner3");
+        assert!(remaining.is_empty());
+
+    }
+
+    #[test]
+    fn test_orphaned_blocks() {
+        let genesis = create_genesis(0);
+
+        let valid_block = create_test_block(&genesis, 42, "miner1");
+        let mut orphan_block = create_test_block(&genesis, 10, "miner2");
+        orphan_block.parent_hash = vec![0xFF; 32]; // Unknown parent hash
+
+        let (blockchain, _) = Blockchain::new_from_genesis_and_vec(
+            genesis,
+            vec![valid_block, orphan_block],
+        );
+
+        // Only valid_block should be added
+        let store = &blockchain.blocks;
+        assert_eq!(store.children(store.root()).count(), 1);
+        assert_eq!(store.children(store.root()).next().unwrap().block().miner, "miner1");
+    }
+
+    #[test]
+    fn test_duplicate_valid_blocks() {
+        let genesis = create_genesis(0);
+
+        let block1 = create_test_block(&genesis, 42, "miner1");
+        let block2 = create_test_block(&genesis, 43, "miner2");
+        let block3 = create_test_block(&block1, 43, "miner3");
+
+        let (blockchain, remaining) = Blockchain::new_from_genesis_and_vec(
+            genesis,
+            vec![block1.clone(), block2, block3, block1],
+        );
+
+        assert!(remaining.is_empty());
+        assert_eq!(blockchain.len(), 4);
+
+        let store = &blockchain.blocks;
+        let root = store.root();
+        assert_eq!(store.children(root).count(), 2); // block1 and block2
+
+        // Find block1 in children
+        let block1_node = store
+            .children(root)
+            .find(|n| n.block().miner == "miner1")
+            .unwrap();
+
+        // block3 shares its nonce with block2 but is a different block
+        assert_eq!(store.children(block1_node).count(), 1);
+    }
+
+    #[test]
+    fn test_complex_structure() {
+        let genesis = create_genesis(0);
+
+        // Create blocks
+        let block1 = create_test_block(&genesis, 42, "miner1");
+        let block2 = create_test_block(&genesis, 43, "miner2");
+        let block3 = create_test_block(&block1, 44, "miner3");
+        let block4 = create_test_block(&block2, 45, "miner4");
+        let block5 = create_test_block(&block2, 46, "miner5");
+
+        let (blockchain, _) = Blockchain::new_from_genesis_and_vec(
+            genesis,
+            vec![block1, block2, block3, block4, block5],
+        );
+
+        // Verify structure
+        let store = &blockchain.blocks;
+        let root = store.root();
+        assert_eq!(store.children(root).count(), 2);
+
+        let block1_node = store
+            .children(root)
+            .find(|n| n.block().miner == "miner1")
+            .unwrap();
+        assert_eq!(store.children(block1_node).count(), 1);
+        assert_eq!(store.children(block1_node).next().unwrap().block().miner, "miner3");
+
+        let block2_node = store
+            .children(root)
+            .find(|n| n.block().miner == "miner2")
+            .unwrap();
+        assert_eq!(store.children(block2_node).count(), 2);
+        assert!(store
+            .children(block2_node)
+            .any(|n| n.block().miner == "miner4"));
+        assert!(store
+            .children(block2_node)
+            .any(|n| n.block().miner == "miner5"));
+    }
+
+    #[test]
+    fn test_multiple_genesis() {
+        let genesis = create_genesis(0);
+        let genesis2 = create_genesis(42);
+
+        let block1 = create_test_block(&genesis, 42, "miner1");
+        let block2 = create_test_block(&genesis, 43, "miner2");
+        let block3 = create_test_block(&block1, 44, "miner3");
+
+        let block4 = create_test_block(&genesis2, 42, "miner1");
+
+        let (_, remaining) = Blockchain::new_from_genesis_and_vec(
+            genesis,
+            vec![block1, block2, block3, block4],
+        );
+
+        assert_eq!(remaining.len(), 1);
+    }
+
+    #[test]
+    fn test_add_block_outcomes() {
+        let genesis = create_genesis(0);
+        let mut blockchain = Blockchain::new(genesis.clone());
+
+        let block1 = create_test_block(&genesis, 42, "miner1");
+        let block2 = create_test_block(&genesis, 42, "miner2");
+
+        assert_eq!(
+            blockchain.add_block(block1.clone()),
+            AddBlockOutcome::Inserted { tip_changed: true }
+        );
+        assert_eq!(blockchain.add_block(block1), AddBlockOutcome::Duplicate);
+        assert_eq!(blockchain.add_block(genesis), AddBlockOutcome::Duplicate);
+        assert_eq!(
+            blockchain.add_block(block2),
+            AddBlockOutcome::Inserted { tip_changed: false }
+        );
+        assert_eq!(
+            blockchain.add_block(create_genesis(1)),
+            AddBlockOutcome::Invalid(InvalidBlock::SecondGenesis)
+        );
+    }
+
+    #[test]
+    fn test_structure_checks() {
+        let genesis = create_genesis(0);
+        let mut blockchain = Blockchain::new(genesis.clone());
+
+        let mut block = create_test_block(&genesis, 1, "miner1");
+        block.parent_hash.truncate(8);
+        assert_eq!(
+            blockchain.add_block(block),
+            AddBlockOutcome::Invalid(InvalidBlock::MalformedParentHash)
+        );
+        // The miner name is only an alias of the public key
+        assert!(matches!(
+            blockchain.add_block(create_test_block(&genesis, 2, "")),
+            AddBlockOutcome::Inserted { .. }
+        ));
+        let long_name = "m".repeat(MAX_MINER_NAME_LEN + 1);
+        assert_eq!(
+            blockchain.add_block(create_test_block(&genesis, 3, &long_name)),
+            AddBlockOutcome::Invalid(InvalidBlock::MinerNameTooLong)
+        );
+        let mut block = create_test_block(&genesis, 4, "miner1");
+        block.version = BLOCK_VERSION + 1;
+        assert_eq!(
+            blockchain.add_block(block),
+            AddBlockOutcome::Invalid(InvalidBlock::UnsupportedVersion)
+        );
+        let transaction = Transaction::new(vec![1; 32], 0, Payload::Data(b"record".to_vec()));
+        let mut block = create_test_block(&genesis, 6, "miner1");
+        block.transactions.push(transaction.clone());
+        assert_eq!(
+            blockchain.add_block(block),
+            AddBlockOutcome::Invalid(InvalidBlock::MerkleRootMismatch)
+        );
+        let mut block = create_test_block(&genesis, 7, "miner1");
+        let empty = Payload::Transfer { to: vec![2; 32], amount: 0 };
+        block.set_transactions(vec![transaction.clone(), Transaction::new(vec![1; 32], 1, empty)]);
+        assert_eq!(
+            blockchain.add_block(block),
+            AddBlockOutcome::Invalid(InvalidBlock::InvalidTransaction(
+                InvalidTransaction::ZeroAmount
+            ))
+        );
+        let mut block = create_test_block(&genesis, 8, "miner1");
+        block.set_transactions(vec![transaction; MAX_BLOCK_TRANSACTIONS + 1]);
+        assert_eq!(
+            blockchain.add_block(block),
+            AddBlockOutcome::Invalid(InvalidBlock::TooManyTransactions)
+        );
+        let max_name = "m".repeat(MAX_MINER_NAME_LEN);
+        assert!(matches!(
+            blockchain.add_block(create_test_block(&genesis, 5, &max_name)),
+            AddBlockOutcome::Inserted { .. }
+        ));
+    }
+
+    #[test]
+    fn test_context_checks() {
+        let genesis = create_genesis(0);
+        let mut blockchain = Blockchain::new(genesis.clone());
+
+        let mut block = create_test_block(&genesis, 1, "miner1");
+        block.height = 2;
+        assert_eq!(
+            blockchain.add_block(block),
+            AddBlockOutcome::Invalid(InvalidBlock::WrongHeight)
+        );
+        let mut block = create_test_block(&genesis, 2, "miner1");
+        block.timestamp = genesis.timestamp;
+        assert_eq!(
+            blockchain.add_block(block),
+            AddBlockOutcome::Invalid(InvalidBlock::TimestampTooOld)
+        );
+        let mut block = create_test_block(&genesis, 3, "miner1");
+        block.timestamp = timestamp_now() + 2 * MAX_FUTURE_DRIFT;
+        assert_eq!(
+            blockchain.add_block(block),
+            AddBlockOutcome::Invalid(InvalidBlock::TimestampTooFarInFuture)
+        );
+        let mut block = create_test_block(&genesis, 4, "miner1");
+        block.bits += 1;
+        assert_eq!(
+            blockchain.add_block(block),
+            AddBlockOutcome::Invalid(InvalidBlock::WrongDifficulty)
+        );
+        assert_eq!(blockchain.len(), 1);
+    }
+
+    #[test]
+    fn test_median_time_past() {
+        let mut genesis = create_genesis(0);
+        genesis.timestamp = 1000;
+        let mut blockchain = Blockchain::new(genesis.clone());
+
+        // Timestamps 1000, 1001, ..., 1011: the median of the last 11 is 1006
+        let mut parent = genesis;
+        for nonce in 1..12 {
+            let mut block = create_test_block(&parent, nonce, "miner1");
+            block.bits = blockchain.next_bits(blockchain.tip());
+            blockchain.add_block(block.clone());
+            parent = block;
+        }
+        assert_eq!(blockchain.tip().height(), 11);
+        assert_eq!(blockchain.median_time_past(blockchain.tip()), 1006);
+
+        // Going back in time is fine, as long as it's after the median
+        let mut block = create_test_block(&parent, 12, "miner1");
+        block.bits = blockchain.next_bits(blockchain.tip());
+        block.timestamp = 1006;
+        assert_eq!(
+            blockchain.add_block(block.clone()),
+            AddBlockOutcome::Invalid(InvalidBlock::TimestampTooOld)
+        );
+        block.timestamp = 1007;
+        assert_eq!(
+            blockchain.add_block(block),
+            AddBlockOutcome::Inserted { tip_changed: true }
+        );
+    }
+
+    #[test]
+    fn test_retargeting() {
+        let params = ChainParams {
+            initial_difficulty: 10,
+            target_block_time: 1000,
+            retarget_window: 4,
+            ..ChainParams::default()
+        };
+        let genesis = create_genesis(0);
+        let mut blockchain = Blockchain::new(genesis.clone()).with_params(params);
+
+        // Mines a block `time` ms after the tip, returns its target
+        let mut mine = |nonce: u64, time: u64| {
+            let tip = blockchain.tip();
+            let mut block = create_test_block(tip.block(), nonce, "miner1");
+            block.timestamp = tip.block().timestamp + time;
+            block.bits = blockchain.next_bits(tip);
+            assert_eq!(
+                blockchain.add_block(block.clone()),
+                AddBlockOutcome::Inserted { tip_changed: true }
+            );
+            block.target().unwrap()
+        };
+
+        // The first window, from the genesis block, is four times too
+        // fast: +2 bits. The second one is twice too slow: -1 bit.
+        let difficulties: Vec<u32> = (1..=7)
+            .map(|nonce| mine(nonce, if nonce < 4 { 250 } else { 2000 }).leading_zeros())
+            .collect();
+        assert_eq!(difficulties, vec![10, 10, 10, 12, 12, 12, 12]);
+        let target = mine(8, 900);
+        assert_eq!(target.leading_zeros(), 11);
+
+        // The third one is 10% too fast
+        for nonce in 9..12 {
+            mine(nonce, 900);
+        }
+        let expected = target.scale(9, 10);
+        assert_eq!(mine(12, 900), Target::from_compact(expected.to_compact()).unwrap());
+
+        // A block that ignores the retargeting is rejected
+        let tip = blockchain.tip().block().clone();
+        let mut block = create_test_block(&tip, 13, "miner1");
+        block.bits = target.to_compact();
+        assert_eq!(
+            blockchain.add_block(block),
+            AddBlockOutcome::Invalid(InvalidBlock::WrongDifficulty)
+        );
+    }
+
+    #[test]
+    fn test_orphans_adopted() {
+        let genesis = create_genesis(0);
+        let mut blockchain = Blockchain::new(genesis.clone());
+
+        let block1 = create_test_block(&genesis, 42, "miner1");
+        let block2 = create_test_block(&block1, 43, "miner2");
+        let block3 = create_test_block(&block2, 44, "miner3");
+        let mut invalid = create_test_block(&block2, 45, "miner4");
+        invalid.height = 42;
+
+        assert_eq!(blockchain.add_block(block3.clone()), AddBlockOutcome::Orphaned);
+        assert_eq!(blockchain.add_block(invalid), AddBlockOutcome::Orphaned);
+        assert_eq!(blockchain.add_block(block2), AddBlockOutcome::Orphaned);
+        assert_eq!(blockchain.add_block(block3), AddBlockOutcome::Duplicate);
+        assert_eq!(blockchain.orphan_count(), 3);
+        assert_eq!(blockchain.check_block(&block1), Ok(()));
+        assert_eq!(blockchain.check_block(&genesis), Err(AddBlockOutcome::Duplicate));
+
+        assert_eq!(
+            blockchain.add_block(block1),
+            AddBlockOutcome::Inserted { tip_changed: true }
+        );
+        // The invalid orphan was dropped
+        assert_eq!(blockchain.orphan_count(), 0);
+        assert_eq!(blockchain.len(), 4);
+        // Orphans are inserted after their parent
+        let inserted: Vec<&str> =
+            blockchain.inserted_since(1).map(|node| node.block().miner.as_str()).collect();
+        assert_eq!(inserted, ["miner1", "miner2", "miner3"]);
+        assert_eq!(blockchain.cursor(), 4);
+        assert_eq!(blockchain.tip().block().miner, "miner3");
+        assert_eq!(blockchain.tip().height(), 3);
+
+        // A shorter fork doesn't move the tip
+        let fork = create_test_block(&genesis, 46, "miner5");
+        assert_eq!(
+            blockchain.add_block(fork),
+            AddBlockOutcome::Inserted { tip_changed: false }
+        );
+        assert_eq!(blockchain.inserted_since(4).count(), 1);
+        assert_eq!(blockchain.inserted_since(5).count(), 0);
+    }
+
+    #[test]
+    fn test_orphan_pool_is_capped() {
+        let genesis = create_genesis(0);
+        let mut blockchain = Blockchain::new(genesis.clone());
+        let orphans: Vec<Block> = (0..=MAX_ORPHANS as u64)
+            .map(|nonce| {
+                let mut block = create_test_block(&genesis, nonce, "miner1");
+                block.parent_hash = nonce.to_le_bytes().repeat(4);
+                block
+            })
+            .collect();
+
+        for orphan in &orphans {
+            assert_eq!(blockchain.add_block(orphan.clone()), AddBlockOutcome::Orphaned);
+        }
+        assert_eq!(blockchain.orphan_count(), MAX_ORPHANS);
+        // The oldest orphan was dropped
+        assert_eq!(blockchain.add_block(orphans[1].clone()), AddBlockOutcome::Duplicate);
+        assert_eq!(blockchain.add_block(orphans[0].clone()), AddBlockOutcome::Orphaned);
+        assert_eq!(blockchain.orphan_count(), MAX_ORPHANS);
+        assert_eq!(blockchain.add_block(orphans[1].clone()), AddBlockOutcome::Orphaned);
+    }
+
+    #[test]
+    fn test_best_chain() {
+        let genesis = create_genesis(0);
+
+        let block1 = create_test_block(&genesis, 42, "miner1");
+        let block2 = create_test_block(&genesis, 43, "miner2");
+        let block3 = create_test_block(&block1, 44, "miner3");
+        let (block1_id, block2_id) = (block1.block_hash(), block2.block_hash());
+
+        let (blockchain, _) = Blockchain::new_from_genesis_and_vec(
+            genesis,
+            vec![block1, block2, block3],
+        );
+
+        let miners: Vec<_> = blockchain
+            .best_chain()
+            .map(|n| n.block().miner.as_str())
+            .collect();
+        assert_eq!(miners, vec!["miner3", "miner1", "Genesis"]);
+
+        assert!(blockchain.is_on_best_chain(&block1_id));
+        assert!(!blockchain.is_on_best_chain(&block2_id));
+        assert_eq!(blockchain.iter().count(), blockchain.len());
+    }
+
+    #[test]
+    fn test_fork_choice() {
+        let genesis = create_genesis(0);
+
+        let block1 = create_test_block(&genesis, 42, "miner1");
+        let block2 = create_test_block(&genesis, 43, "miner2");
+        let block3 = create_test_block(&block1, 44, "miner3");
+        let blocks = vec![block1, block2, block3];
+
+        let (blockchain, _) = Blockchain::new_from_genesis_and_vec(genesis.clone(), blocks.clone());
+        assert_eq!(blockchain.tip().block().miner, "miner3");
+        let work = Target::from_leading_zeros(DIFFICULTY).work();
+        assert_eq!(blockchain.tip().chainwork(), 2 * work);
+
+        let easy_block = |parent: &Block, nonce, miner: &str| Block {
+            bits: Target::from_leading_zeros(4).to_compact(),
+            ..create_test_block(parent, nonce, miner)
+        };
+        let block1 = easy_block(&genesis, 42, "miner1");
+        let block2 = easy_block(&genesis, 43, "miner2");
+        let block3 = easy_block(&block1, 44, "miner3");
+
+        let mut blockchain = Blockchain::new(genesis.clone())
+            .with_fork_choice(Box::new(LongestChain))
+            .with_params(ChainParams {
+                initial_difficulty: 4,
+                ..ChainParams::default()
+            });
+        for block in [block1, block2, block3] {
+            blockchain.add_block(block);
+        }
+        assert_eq!(blockchain.tip().block().miner, "miner3");
+        assert_eq!(blockchain.tip().chainwork(), 32);
+
+        blockchain.reset(genesis);
+        assert_eq!(blockchain.len(), 1);
+        assert_eq!(blockchain.tip().block().miner, "Genesis");
+    }
+
+    #[test]
+    fn test_ledger_follows_the_tip() {
+        let genesis = create_genesis(0);
+        let mut blockchain = Blockchain::new(genesis.clone());
+        let reward = blockchain.params().block_reward;
+        let mined_by = |parent: &Block, nonce, key: u8, transactions| {
+            let mut block = create_test_block(parent, nonce, "miner1");
+            block.public_key = vec![key; 32];
+            block.set_transactions(transactions);
+            block
+        };
+        let pay = |from: u8, nonce, to: u8, amount| {
+            let payload = Payload::Transfer { to: vec![to; 32], amount };
+            Transaction::new(vec![from; 32], nonce, payload)
+        };
+
+        let a1 = mined_by(&genesis, 1, 1, vec![]);
+        let a2 = mined_by(&a1, 2, 1, vec![pay(1, 0, 2, 70)]);
+        blockchain.add_block(a1);
+        blockchain.add_block(a2.clone());
+        let account = |chain: &Blockchain, key: u8| chain.ledger().account(&[key; 32]);
+        assert_eq!(account(&blockchain, 1), Account { balance: 2 * reward - 70, nonce: 1 });
+        assert_eq!(account(&blockchain, 2).balance, 70);
+        let transfer = pay(1, 0, 2, 70).txid();
+        let found = blockchain.find_transaction(&transfer).map(|(node, index)| (*node.hash(), index));
+        assert_eq!(found, Some((a2.block_hash(), 0)));
+
+        let overspending = mined_by(&a2, 3, 1, vec![pay(1, 1, 2, 2 * reward)]);
+        assert_eq!(
+            blockchain.add_block(overspending),
+            AddBlockOutcome::Invalid(InvalidBlock::InvalidTransaction(
+                InvalidTransaction::InsufficientBalance
+            ))
+        );
+
+        // A longer branch without the transfer takes over
+        let b1 = mined_by(&genesis, 11, 3, vec![]);
+        let b2 = mined_by(&b1, 12, 3, vec![]);
+        let b3 = mined_by(&b2, 13, 3, vec![]);
+        for block in [b1.clone(), b2.clone(), b3.clone()] {
+            blockchain.add_block(block);
+        }
+        assert_eq!(blockchain.tip().hash(), &b3.block_hash());
+        assert_eq!(account(&blockchain, 1), Account::default());
+        assert_eq!(account(&blockchain, 2), Account::default());
+        assert_eq!(account(&blockchain, 3).balance, 3 * reward);
+        assert!(blockchain.find_transaction(&transfer).is_none());
+        let (replayed, _) = Blockchain::new_from_genesis_and_vec(genesis, vec![b1, b2, b3.clone()]);
+        assert_eq!(blockchain.ledger(), replayed.ledger());
+
+        // The transfer was undone, it can't be spent again on this branch
+        assert_eq!(
+            blockchain.add_block(mined_by(&b3, 14, 3, vec![pay(1, 1, 2, 10)])),
+            AddBlockOutcome::Invalid(InvalidBlock::InvalidTransaction(
+                InvalidTransaction::WrongNonce
+            ))
+        );
+        let a2 = blockchain.get(&a2.block_hash()).unwrap();
+        assert_eq!(blockchain.ledger_at(a2).unwrap().account(&[2; 32]).balance, 70);
+
+        let balances = blockchain.balances();
+        assert_eq!(balances[0].public_key, "03".repeat(32));
+        assert_eq!(balances[0].miner, "miner1");
+        assert_eq!(balances[0].balance, 3 * reward);
+    }
+
+    #[test]
+    fn test_params_change_drops_invalid_blocks() {
+        let genesis = create_genesis(0);
+        let mut blockchain = Blockchain::new(genesis.clone());
+        let payment = Transaction::new(vec![1; 32], 0, Payload::Transfer { to: vec![2; 32], amount: 70 });
+        let mut a1 = create_test_block(&genesis, 1, "miner1");
+        a1.public_key = vec![1; 32];
+        let mut a2 = create_test_block(&a1, 2, "miner1");
+        a2.public_key = vec![1; 32];
+        a2.set_transactions(vec![payment]);
+        blockchain.add_block(a1.clone());
+        assert_eq!(blockchain.add_block(a2), AddBlockOutcome::Inserted { tip_changed: true });
+
+        // The transfer is more than two rewards now
+        let blockchain = blockchain.with_params(ChainParams {
+            block_reward: 30,
+            ..ChainParams::default()
+        });
+        assert_eq!(blockchain.len(), 2);
+        assert_eq!(blockchain.tip().hash(), &a1.block_hash());
+        assert_eq!(blockchain.ledger().account(&[1; 32]).balance, 30);
+    }
+}
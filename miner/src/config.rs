@@ -0,0 +1,239 @@
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{env, fmt, fs, io};
+
+/// File the network settings are read from when no other one is given,
+/// if it exists.
+pub const DEFAULT_CONFIG_FILE: &str = "miner.toml";
+
+/// File the mined blocks wait in until a server takes them, when no other
+/// one is given.
+pub const DEFAULT_OUTBOX_FILE: &str = "miner.outbox";
+
+/// Settings of the connection to the servers, read from a TOML file like:
+///
+/// ```toml
+/// servers = ["http://localhost:8080", "http://backup:8080"]
+/// poll_interval = 1000
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    /// URLs of the servers. Requests go to the first one, and to the next
+    /// ones while it doesn't answer.
+    pub servers: Vec<String>,
+    /// Time between two polls of the blocks of the server, in milliseconds
+    pub poll_interval: u64,
+    /// Time to connect to a server, in milliseconds
+    pub connect_timeout: u64,
+    /// Time to get the whole answer to a request, in milliseconds
+    pub timeout: u64,
+    /// Longest time between two retries while the servers don't answer, in
+    /// milliseconds. The time starts at the poll interval and doubles after
+    /// each failure.
+    pub max_backoff: u64,
+    /// File the mined blocks wait in until a server takes them
+    pub outbox: PathBuf,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            servers: vec!["http://localhost:8080".to_string()],
+            poll_interval: 1000,
+            connect_timeout: 2000,
+            timeout: 10_000,
+            max_backoff: 30_000,
+            outbox: PathBuf::from(DEFAULT_OUTBOX_FILE),
+        }
+    }
+}
+
+/// Why the network settings couldn't be loaded.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The config file can't be read.
+    Io(PathBuf, io::Error),
+    /// The config file isn't valid.
+    Parse(PathBuf, Box<toml::de::Error>),
+    /// An environment variable can't be parsed.
+    InvalidVariable(&'static str, String),
+    /// The list of servers is empty.
+    NoServer,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "can't read {:?}: {}", path, e),
+            ConfigError::Parse(path, e) => write!(f, "invalid config file {:?}: {}", path, e),
+            ConfigError::InvalidVariable(name, value) => {
+                write!(f, "invalid value {:?} of the variable {}", value, name)
+            }
+            ConfigError::NoServer => write!(f, "no server to connect to"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Command line flags of the network settings. They override the
+/// environment variables, which override the config file.
+#[derive(Debug, Clone, clap::Args)]
+pub struct NetworkArgs {
+    /// TOML file with the network settings [default: miner.toml, if it exists]
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// URL of a server. Several ones are tried in order [env: MINER_SERVERS]
+    #[arg(long = "server", value_delimiter = ',')]
+    servers: Vec<String>,
+    /// Time between two polls of the server, in milliseconds [env: MINER_POLL_INTERVAL]
+    #[arg(long)]
+    poll_interval: Option<u64>,
+    /// Time to connect to a server, in milliseconds [env: MINER_CONNECT_TIMEOUT]
+    #[arg(long)]
+    connect_timeout: Option<u64>,
+    /// Time to get the answer to a request, in milliseconds [env: MINER_TIMEOUT]
+    #[arg(long)]
+    timeout: Option<u64>,
+    /// Longest time between two retries, in milliseconds [env: MINER_MAX_BACKOFF]
+    #[arg(long)]
+    max_backoff: Option<u64>,
+    /// File the mined blocks wait in until a server takes them [env: MINER_OUTBOX]
+    #[arg(long)]
+    outbox: Option<PathBuf>,
+}
+
+impl NetworkArgs {
+    /// Loads the settings: the defaults, overridden by the config file,
+    /// then by the environment, then by the flags
+    pub fn load(&self) -> Result<NetworkConfig, ConfigError> {
+        let mut config = match &self.config {
+            Some(path) => NetworkConfig::read(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                NetworkConfig::read(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => NetworkConfig::default(),
+        };
+        config.override_with(|name| env::var(name).ok())?;
+
+        if !self.servers.is_empty() {
+            config.servers = self.servers.clone();
+        }
+        config.poll_interval = self.poll_interval.unwrap_or(config.poll_interval);
+        config.connect_timeout = self.connect_timeout.unwrap_or(config.connect_timeout);
+        config.timeout = self.timeout.unwrap_or(config.timeout);
+        config.max_backoff = self.max_backoff.unwrap_or(config.max_backoff);
+        config.outbox = self.outbox.clone().unwrap_or(config.outbox);
+
+        if config.servers.is_empty() {
+            return Err(ConfigError::NoServer);
+        }
+        Ok(config)
+    }
+}
+
+impl NetworkConfig {
+    /// Reads a TOML config file. The settings it doesn't set keep their
+    /// default value.
+    pub fn read(path: &Path) -> Result<Self, ConfigError> {
+        let text = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_owned(), e))?;
+        toml::from_str(&text).map_err(|e| ConfigError::Parse(path.to_owned(), Box::new(e)))
+    }
+
+    /// Overrides the settings with the `MINER_*` environment variables,
+    /// looked up with `var`
+    fn override_with(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), ConfigError> {
+        if let Some(servers) = var("MINER_SERVERS") {
+            self.servers = servers
+                .split(',')
+                .map(str::trim)
+                .filter(|server| !server.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        let millis = |name: &'static str, value: &mut u64| {
+            if let Some(text) = var(name) {
+                *value = text.trim().parse().map_err(|_| ConfigError::InvalidVariable(name, text))?;
+            }
+            Ok(())
+        };
+        millis("MINER_POLL_INTERVAL", &mut self.poll_interval)?;
+        millis("MINER_CONNECT_TIMEOUT", &mut self.connect_timeout)?;
+        millis("MINER_TIMEOUT", &mut self.timeout)?;
+        millis("MINER_MAX_BACKOFF", &mut self.max_backoff)?;
+        if let Some(outbox) = var("MINER_OUTBOX") {
+            self.outbox = PathBuf::from(outbox);
+        }
+        Ok(())
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval)
+    }
+
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_millis(self.connect_timeout)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout)
+    }
+
+    /// Time to wait before retrying after `failures` failed requests in a
+    /// row: the poll interval, doubled after each failure up to the
+    /// maximum backoff
+    pub fn backoff(&self, failures: u32) -> Duration {
+        let backoff = self.poll_interval.saturating_mul(1 << failures.min(32));
+        Duration::from_millis(backoff.min(self.max_backoff.max(self.poll_interval)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_parse() {
+        let config: NetworkConfig = toml::from_str(
+            r#"
+            servers = ["http://a:8080", "http://b:8080"]
+            timeout = 500
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.servers, ["http://a:8080", "http://b:8080"]);
+        assert_eq!(config.timeout, 500);
+        assert_eq!(config.poll_interval, NetworkConfig::default().poll_interval);
+
+        assert_eq!(toml::from_str::<NetworkConfig>("").unwrap(), NetworkConfig::default());
+        assert!(toml::from_str::<NetworkConfig>("server = \"http://a:8080\"").is_err());
+    }
+
+    #[test]
+    fn test_override_with_env() {
+        let env = HashMap::from([
+            ("MINER_SERVERS", "http://a:8080, http://b:8080,"),
+            ("MINER_POLL_INTERVAL", "250"),
+            ("MINER_OUTBOX", "/tmp/outbox"),
+        ]);
+        let mut config = NetworkConfig::default();
+        config.override_with(|name| env.get(name).map(|value| value.to_string())).unwrap();
+        assert_eq!(config.servers, ["http://a:8080", "http://b:8080"]);
+        assert_eq!(config.poll_interval, 250);
+        assert_eq!(config.outbox, Path::new("/tmp/outbox"));
+        assert_eq!(config.timeout, NetworkConfig::default().timeout);
+
+        assert_eq!(config.backoff(0), Duration::from_millis(250));
+        assert_eq!(config.backoff(3), Duration::from_millis(2000));
+        assert_eq!(config.backoff(100), Duration::from_millis(config.max_backoff));
+
+        let invalid = |name: &str| (name == "MINER_TIMEOUT").then(|| "10s".to_string());
+        assert!(matches!(
+            config.override_with(invalid),
+            Err(ConfigError::InvalidVariable("MINER_TIMEOUT", _))
+        ));
+    }
+}
@@ -0,0 +1,489 @@
+use crate::block::{Block, DanceMove, BLOCK_VERSION};
+use crate::transaction::{Payload, Transaction};
+use std::fmt;
+
+/// Why bytes couldn't be decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The bytes end in the middle of a field.
+    UnexpectedEnd,
+    /// There are bytes left after the end of the block.
+    TrailingBytes,
+    /// The encoding of this block version is unknown.
+    UnsupportedVersion(u32),
+    /// A length doesn't fit in 64 bits, or isn't encoded on as few bytes
+    /// as possible.
+    InvalidLength,
+    /// The miner name isn't valid UTF-8.
+    InvalidMinerName,
+    /// The byte doesn't encode a dance move.
+    InvalidDanceMove(u8),
+    /// The byte doesn't encode a kind of transaction payload.
+    InvalidPayload(u8),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of the bytes"),
+            DecodeError::TrailingBytes => write!(f, "trailing bytes after the block"),
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported block version {}", version)
+            }
+            DecodeError::InvalidLength => write!(f, "invalid or non canonical length"),
+            DecodeError::InvalidMinerName => write!(f, "the miner name isn't valid UTF-8"),
+            DecodeError::InvalidDanceMove(byte) => write!(f, "invalid dance move {:#04x}", byte),
+            DecodeError::InvalidPayload(byte) => {
+                write!(f, "invalid transaction payload {:#04x}", byte)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Lowercase hexadecimal representation of bytes
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Parses 32 bytes from 64 hexadecimal digits, like a hash or a key
+pub fn parse_hex(s: &str) -> Option<[u8; 32]> {
+    // from_str_radix alone would accept a sign
+    if s.len() != 64 || !s.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut bytes = [0; 32];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+/// Appends the canonical encoding of the fields of a block to a buffer.
+#[derive(Debug, Default)]
+pub struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    /// Unsigned LEB128: 7 bits per byte, least significant first, the top
+    /// bit set on all the bytes but the last
+    pub fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.bytes.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.bytes.push(value as u8);
+    }
+
+    /// Length as a varint, then the bytes
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.varint(bytes.len() as u64);
+        self.bytes.extend(bytes);
+    }
+
+    /// The bytes of a fixed size field, without length
+    pub fn array<const N: usize>(&mut self, bytes: &[u8; N]) {
+        self.bytes.extend(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Reads the fields written by an [`Encoder`].
+#[derive(Debug)]
+pub struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Decoder { bytes }
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        let (taken, rest) = self
+            .bytes
+            .split_first_chunk()
+            .ok_or(DecodeError::UnexpectedEnd)?;
+        self.bytes = rest;
+        Ok(*taken)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        self.take()
+    }
+
+    pub fn u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    /// Rejects the varints that are too large or that have useless
+    /// trailing zero groups, so each value has a single encoding
+    pub fn varint(&mut self) -> Result<u64, DecodeError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            let group = u64::from(byte & 0x7f);
+            if group << shift >> shift != group {
+                return Err(DecodeError::InvalidLength);
+            }
+            value |= group << shift;
+            if byte & 0x80 == 0 {
+                if byte == 0 && shift > 0 {
+                    return Err(DecodeError::InvalidLength);
+                }
+                return Ok(value);
+            }
+        }
+        Err(DecodeError::InvalidLength)
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], DecodeError> {
+        let len = usize::try_from(self.varint()?).map_err(|_| DecodeError::InvalidLength)?;
+        if len > self.bytes.len() {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (bytes, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    /// Fails if there are bytes left
+    pub fn finish(self) -> Result<(), DecodeError> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(DecodeError::TrailingBytes)
+        }
+    }
+}
+
+impl DanceMove {
+    fn from_u8(byte: u8) -> Result<Self, DecodeError> {
+        match byte {
+            1 => Ok(DanceMove::Y),
+            2 => Ok(DanceMove::M),
+            3 => Ok(DanceMove::C),
+            4 => Ok(DanceMove::A),
+            _ => Err(DecodeError::InvalidDanceMove(byte)),
+        }
+    }
+}
+
+impl Payload {
+    fn encode_into(&self, encoder: &mut Encoder) {
+        match self {
+            Payload::Transfer { to, amount } => {
+                encoder.u8(1);
+                encoder.bytes(to);
+                encoder.u64(*amount);
+            }
+            Payload::Data(data) => {
+                encoder.u8(2);
+                encoder.bytes(data);
+            }
+        }
+    }
+
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        match decoder.u8()? {
+            1 => Ok(Payload::Transfer {
+                to: decoder.bytes()?.to_vec(),
+                amount: decoder.u64()?,
+            }),
+            2 => Ok(Payload::Data(decoder.bytes()?.to_vec())),
+            byte => Err(DecodeError::InvalidPayload(byte)),
+        }
+    }
+}
+
+impl Transaction {
+    /// Canonical binary encoding of the transaction without its signature,
+    /// signed by the sender:
+    ///
+    /// - `from`: length as a varint, then the bytes
+    /// - `nonce`: u64, little endian
+    /// - `payload`: u8 kind, then for a transfer (1) `to` as a varint length
+    ///   and the bytes and `amount` as a u64, for data (2) the data as a
+    ///   varint length and the bytes
+    pub fn encode_unsigned(&self) -> Vec<u8> {
+        let mut encoder = Encoder::default();
+        self.encode_unsigned_into(&mut encoder);
+        encoder.finish()
+    }
+
+    fn encode_unsigned_into(&self, encoder: &mut Encoder) {
+        encoder.bytes(&self.from);
+        encoder.u64(self.nonce);
+        self.payload.encode_into(encoder);
+    }
+
+    fn encode_into(&self, encoder: &mut Encoder) {
+        self.encode_unsigned_into(encoder);
+        encoder.bytes(&self.signature);
+    }
+
+    /// Canonical binary encoding of the transaction: the unsigned encoding,
+    /// then the signature as a varint length and the bytes.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoder = Encoder::default();
+        self.encode_into(&mut encoder);
+        encoder.finish()
+    }
+
+    fn decode_from(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(Transaction {
+            from: decoder.bytes()?.to_vec(),
+            nonce: decoder.u64()?,
+            payload: Payload::decode_from(decoder)?,
+            signature: decoder.bytes()?.to_vec(),
+        })
+    }
+
+    /// Decodes a transaction encoded by [`Transaction::encode`]
+    pub fn decode(bytes: &[u8]) -> Result<Transaction, DecodeError> {
+        let mut decoder = Decoder::new(bytes);
+        let transaction = Transaction::decode_from(&mut decoder)?;
+        decoder.finish()?;
+        Ok(transaction)
+    }
+}
+
+impl Block {
+    /// Canonical binary encoding of the header, hashed to identify the
+    /// block and signed by the miner. Version 3 is, in order:
+    ///
+    /// - `version`: u32, little endian
+    /// - `parent_hash`: length as an unsigned LEB128 varint, then the bytes
+    /// - `merkle_root`: the 32 bytes
+    /// - `miner`: length as a varint, then the UTF-8 bytes
+    /// - `public_key`: length as a varint, then the bytes
+    /// - `nonce`: u64, little endian
+    /// - `dancemove`: u8, Y = 1, M = 2, C = 3, A = 4
+    /// - `timestamp`, `height`: u64, little endian
+    /// - `bits`: u32, little endian
+    pub fn encode_header(&self) -> Vec<u8> {
+        let mut encoder = Encoder::default();
+        self.encode_header_into(&mut encoder);
+        encoder.finish()
+    }
+
+    fn encode_header_into(&self, encoder: &mut Encoder) {
+        encoder.u32(self.version);
+        encoder.bytes(&self.parent_hash);
+        encoder.array(&self.merkle_root);
+        encoder.bytes(self.miner.as_bytes());
+        encoder.bytes(&self.public_key);
+        encoder.u64(self.nonce);
+        encoder.u8(self.dancemove as u8);
+        encoder.u64(self.timestamp);
+        encoder.u64(self.height);
+        encoder.u32(self.bits);
+    }
+
+    /// Canonical binary encoding of the whole block, sent on the wire: the
+    /// header, the `signature` as a varint length and the bytes, then the
+    /// number of transactions as a varint and the transactions, see
+    /// [`Transaction::encode`].
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoder = Encoder::default();
+        self.encode_header_into(&mut encoder);
+        encoder.bytes(&self.signature);
+        encoder.varint(self.transactions.len() as u64);
+        for transaction in &self.transactions {
+            transaction.encode_into(&mut encoder);
+        }
+        encoder.finish()
+    }
+
+    /// Decodes a block encoded by [`Block::encode`]. Each block has a
+    /// single encoding: anything else is rejected.
+    pub fn decode(bytes: &[u8]) -> Result<Block, DecodeError> {
+        let mut decoder = Decoder::new(bytes);
+        let version = decoder.u32()?;
+        if version != BLOCK_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+        let parent_hash = decoder.bytes()?.to_vec();
+        let merkle_root = decoder.array()?;
+        let miner = String::from_utf8(decoder.bytes()?.to_vec())
+            .map_err(|_| DecodeError::InvalidMinerName)?;
+        let mut block = Block {
+            version,
+            parent_hash,
+            merkle_root,
+            miner,
+            public_key: decoder.bytes()?.to_vec(),
+            nonce: decoder.u64()?,
+            dancemove: DanceMove::from_u8(decoder.u8()?)?,
+            timestamp: decoder.u64()?,
+            height: decoder.u64()?,
+            bits: decoder.u32()?,
+            signature: decoder.bytes()?.to_vec(),
+            transactions: Vec::new(),
+        };
+        // Each transaction takes several bytes, the count can't make us
+        // loop much longer than the input
+        for _ in 0..decoder.varint()? {
+            block.transactions.push(Transaction::decode_from(&mut decoder)?);
+        }
+        decoder.finish()?;
+        Ok(block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::check_structure;
+    use serde::Deserialize;
+
+    /// A block with its expected encoding and hash, in hex
+    #[derive(Deserialize)]
+    struct Vector {
+        block: Block,
+        encoding: String,
+        hash: String,
+    }
+
+    #[test]
+    fn test_golden_vectors() {
+        let vectors: Vec<Vector> =
+            serde_json::from_str(include_str!("../testdata/block_vectors.json")).unwrap();
+        assert!(!vectors.is_empty());
+
+        for vector in vectors {
+            let encoding = vector.block.encode();
+            assert_eq!(to_hex(&encoding), vector.encoding);
+            // The hash covers the header, everything but the signature
+            let header = vector.block.encode_header();
+            assert_eq!(encoding[..header.len()], header);
+            assert_eq!(vector.block.block_hash().to_string(), vector.hash);
+            assert_eq!(vector.block.verify_signatures(), Ok(()));
+            assert_eq!(check_structure(&vector.block), Ok(()));
+            assert_eq!(Block::decode(&encoding), Ok(vector.block));
+        }
+    }
+
+    #[test]
+    fn test_no_ambiguous_preimage() {
+        // Moving bytes from the parent hash to the miner name changes the hash
+        let block1 = Block::new(b"ab".to_vec(), "c".to_string(), 0, DanceMove::Y);
+        let block2 = Block::new(b"a".to_vec(), "bc".to_string(), 0, DanceMove::Y);
+        assert_ne!(block1.encode(), block2.encode());
+        assert_ne!(block1.hash_block(), block2.hash_block());
+    }
+
+    #[test]
+    fn test_varint() {
+        for (value, hex) in [(0, "00"), (127, "7f"), (128, "8001"), (300, "ac02")] {
+            let mut encoder = Encoder::default();
+            encoder.varint(value);
+            let bytes = encoder.finish();
+            assert_eq!(to_hex(&bytes), hex);
+            assert_eq!(Decoder::new(&bytes).varint(), Ok(value));
+        }
+
+        let mut encoder = Encoder::default();
+        encoder.varint(u64::MAX);
+        let bytes = encoder.finish();
+        assert_eq!(bytes.len(), 10);
+        assert_eq!(Decoder::new(&bytes).varint(), Ok(u64::MAX));
+
+        // Not minimal
+        assert_eq!(Decoder::new(&[0x80, 0x00]).varint(), Err(DecodeError::InvalidLength));
+        // More than 64 bits
+        let too_large = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02];
+        assert_eq!(Decoder::new(&too_large).varint(), Err(DecodeError::InvalidLength));
+        assert_eq!(Decoder::new(&[0x80]).varint(), Err(DecodeError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn test_transactions() {
+        let transfer = Transaction::new(
+            vec![1; 32],
+            3,
+            Payload::Transfer { to: vec![2; 32], amount: 300 },
+        );
+        let data = Transaction::new(vec![2; 32], 0, Payload::Data(b"audit".to_vec()));
+        for transaction in [&transfer, &data] {
+            assert_eq!(Transaction::decode(&transaction.encode()).as_ref(), Ok(transaction));
+        }
+
+        let mut block = Block::new(vec![0xab; 32], "miner1".to_string(), 42, DanceMove::A);
+        block.set_transactions(vec![transfer, data]);
+        assert_eq!(Block::decode(&block.encode()), Ok(block.clone()));
+
+        // The payload kind comes after the sender and the nonce
+        let mut invalid = block.transactions[0].encode();
+        invalid[33 + 8] = 3;
+        assert_eq!(Transaction::decode(&invalid), Err(DecodeError::InvalidPayload(3)));
+    }
+
+    #[test]
+    fn test_parse_hex() {
+        let bytes: [u8; 32] = std::array::from_fn(|i| i as u8 * 8);
+        assert_eq!(parse_hex(&to_hex(&bytes)), Some(bytes));
+        assert_eq!(parse_hex(&to_hex(&bytes).to_uppercase()), Some(bytes));
+
+        // A single form per byte, no sign nor space
+        let hex = "f".repeat(64);
+        assert_eq!(parse_hex(&hex), Some([0xff; 32]));
+        assert_eq!(parse_hex(&format!("+f{}", &hex[2..])), None);
+        assert_eq!(parse_hex(&format!("-f{}", &hex[2..])), None);
+        assert_eq!(parse_hex(&format!(" f{}", &hex[2..])), None);
+        assert_eq!(parse_hex(&hex[1..]), None);
+        assert_eq!(parse_hex(&format!("{}0", hex)), None);
+        assert_eq!(parse_hex(&format!("é{}", &hex[2..])), None);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let block = Block::new(vec![0xab; 32], "miner1".to_string(), 42, DanceMove::A);
+        let bytes = block.encode();
+
+        assert_eq!(Block::decode(&bytes[..bytes.len() - 1]), Err(DecodeError::UnexpectedEnd));
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(Block::decode(&trailing), Err(DecodeError::TrailingBytes));
+
+        let mut version = bytes.clone();
+        version[0] = 7;
+        assert_eq!(Block::decode(&version), Err(DecodeError::UnsupportedVersion(7)));
+
+        // The dance move comes after the version, the parent hash, the
+        // Merkle root, the miner name, the empty public key and the nonce
+        let dancemove = 4 + 33 + 32 + 7 + 1 + 8;
+        let mut invalid = bytes.clone();
+        invalid[dancemove] = 5;
+        assert_eq!(Block::decode(&invalid), Err(DecodeError::InvalidDanceMove(5)));
+
+        let mut invalid = bytes;
+        invalid[4 + 33 + 32 + 1] = 0xff;
+        assert_eq!(Block::decode(&invalid), Err(DecodeError::InvalidMinerName));
+    }
+}
@@ -0,0 +1,149 @@
+use base64::Engine;
+use miner::sync::{ChainEvent, TIP_EVENT_INTERVAL_SECONDS};
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Largest message accepted from the server, in bytes.
+const MAX_MESSAGE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Subscription to the events a server pushes on the WebSocket of
+/// `GET /events`. Only the part of the protocol the server uses is
+/// supported: plain HTTP, no extension, and the client never sends any
+/// message.
+pub struct Subscription {
+    stream: BufReader<TcpStream>,
+}
+
+impl Subscription {
+    /// Opens the WebSocket of the server at `server`, like
+    /// `http://localhost:8080`
+    pub fn connect(server: &str, connect_timeout: Duration) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(ErrorKind::InvalidInput, message.to_string());
+        let url = reqwest::Url::parse(server).map_err(|e| invalid(&e.to_string()))?;
+        if url.scheme() != "http" {
+            return Err(invalid("events are only pushed over http"));
+        }
+        let host = url.host_str().ok_or_else(|| invalid("no host"))?;
+        let port = url.port_or_known_default().unwrap_or(80);
+
+        let mut failure = invalid("no address");
+        let mut stream = None;
+        for address in (host, port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, connect_timeout) {
+                Ok(connected) => {
+                    stream = Some(connected);
+                    break;
+                }
+                Err(e) => failure = e,
+            }
+        }
+        let mut stream = stream.ok_or(failure)?;
+        // The server pushes the tip regularly, so a silent server is gone
+        stream.set_read_timeout(Some(Duration::from_secs(3 * TIP_EVENT_INTERVAL_SECONDS)))?;
+
+        let key = base64::engine::general_purpose::STANDARD.encode(rand::random::<[u8; 16]>());
+        write!(
+            stream,
+            "GET {}/events HTTP/1.1\r\nHost: {}:{}\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\
+             Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: {}\r\n\r\n",
+            url.path().trim_end_matches('/'),
+            host,
+            port,
+            key
+        )?;
+
+        let mut stream = BufReader::new(stream);
+        let mut status = String::new();
+        stream.read_line(&mut status)?;
+        if status.split_whitespace().nth(1) != Some("101") {
+            return Err(io::Error::other(format!("the server answered {:?}", status.trim())));
+        }
+        // Skip the headers
+        let mut line = String::new();
+        while line != "\r\n" {
+            line.clear();
+            if stream.read_line(&mut line)? == 0 {
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+        }
+        Ok(Subscription { stream })
+    }
+
+    /// Waits for the next event. Fails if the connection is lost, or if the
+    /// server sends nothing for a while.
+    pub fn next_event(&mut self) -> io::Result<ChainEvent> {
+        let message = read_message(&mut self.stream)?;
+        Ok(serde_json::from_slice(&message)?)
+    }
+}
+
+/// Reads the frames of the next data message, and returns its payload
+fn read_message(stream: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut message = Vec::new();
+    loop {
+        let mut header = [0; 2];
+        stream.read_exact(&mut header)?;
+        let fin = header[0] & 0x80 != 0;
+        let opcode = header[0] & 0x0f;
+        let len = match header[1] & 0x7f {
+            126 => {
+                let mut len = [0; 2];
+                stream.read_exact(&mut len)?;
+                u16::from_be_bytes(len) as u64
+            }
+            127 => {
+                let mut len = [0; 8];
+                stream.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            }
+            len => len as u64,
+        };
+        let mut mask = [0; 4];
+        if header[1] & 0x80 != 0 {
+            stream.read_exact(&mut mask)?;
+        }
+        if message.len() as u64 + len > MAX_MESSAGE_SIZE {
+            return Err(io::Error::new(ErrorKind::InvalidData, "message too large"));
+        }
+
+        let mut payload = vec![0; len as usize];
+        stream.read_exact(&mut payload)?;
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+        match opcode {
+            0x8 => return Err(io::Error::new(ErrorKind::ConnectionAborted, "closed by the server")),
+            // Pings and pongs, between the frames of a message
+            0x9 | 0xa => continue,
+            _ => message.extend(payload),
+        }
+        if fin {
+            return Ok(message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_message() {
+        let long = vec![b'a'; 300];
+        let mut frames = vec![0x81, 5];
+        frames.extend(b"hello");
+        // A message in two frames, with a ping in between
+        frames.extend([0x01, 126, 0x01, 0x2c]);
+        frames.extend(&long);
+        frames.extend([0x89, 0, 0x80, 0x82, 1, 2, 3, 4]);
+        frames.extend([b'!' ^ 1, b'?' ^ 2]);
+        frames.extend([0x88, 0]);
+
+        let mut stream = &frames[..];
+        assert_eq!(read_message(&mut stream).unwrap(), b"hello");
+        assert_eq!(read_message(&mut stream).unwrap(), [&long[..], b"!?"].concat());
+        assert_eq!(read_message(&mut stream).unwrap_err().kind(), ErrorKind::ConnectionAborted);
+        assert_eq!(read_message(&mut stream).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,183 @@
+use crate::block::{BlockHash, BlockIdHasher};
+use crate::blockstore::{BlockNode, BlockStore};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A rule choosing the tip new blocks should be mined on.
+pub trait ForkChoice: fmt::Debug + Send + Sync {
+    /// Computes the best tip of the store from scratch
+    fn best_tip(&self, store: &BlockStore) -> BlockHash;
+
+    /// Returns the best tip after `inserted` was added to the store, given
+    /// `current` was the best tip before. Recomputes from scratch by default.
+    fn update(&self, store: &BlockStore, current: &BlockHash, inserted: &BlockHash) -> BlockHash {
+        let _ = (current, inserted);
+        self.best_tip(store)
+    }
+}
+
+/// Best tip among all the blocks, according to a key to maximize.
+fn max_by_key<K: Ord>(store: &BlockStore, key: impl Fn(&BlockNode) -> K) -> BlockHash {
+    *store.iter().max_by_key(|node| key(node)).unwrap().hash()
+}
+
+/// Best of the current tip and of the inserted block. Enough for rules
+/// where a block is always better than its ancestors.
+fn max_of_two<K: Ord>(
+    store: &BlockStore,
+    current: &BlockHash,
+    inserted: &BlockHash,
+    key: impl Fn(&BlockNode) -> K,
+) -> BlockHash {
+    match (store.get(current), store.get(inserted)) {
+        (Some(current), Some(inserted)) if key(inserted) > key(current) => *inserted.hash(),
+        (Some(current), _) => *current.hash(),
+        (None, _) => max_by_key(store, key),
+    }
+}
+
+/// The deepest block, the one with the lowest nonce in case of tie.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LongestChain;
+
+impl LongestChain {
+    fn key(node: &BlockNode) -> (u64, Reverse<u64>) {
+        (node.height(), Reverse(node.block().nonce))
+    }
+}
+
+impl ForkChoice for LongestChain {
+    fn best_tip(&self, store: &BlockStore) -> BlockHash {
+        max_by_key(store, Self::key)
+    }
+
+    fn update(&self, store: &BlockStore, current: &BlockHash, inserted: &BlockHash) -> BlockHash {
+        max_of_two(store, current, inserted, Self::key)
+    }
+}
+
+/// The block with the most accumulated work, then the deepest one, then
+/// the one with the lowest nonce.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HeaviestWork;
+
+impl HeaviestWork {
+    fn key(node: &BlockNode) -> (u128, u64, Reverse<u64>) {
+        (node.chainwork(), node.height(), Reverse(node.block().nonce))
+    }
+}
+
+impl ForkChoice for HeaviestWork {
+    fn best_tip(&self, store: &BlockStore) -> BlockHash {
+        max_by_key(store, Self::key)
+    }
+
+    fn update(&self, store: &BlockStore, current: &BlockHash, inserted: &BlockHash) -> BlockHash {
+        max_of_two(store, current, inserted, Self::key)
+    }
+}
+
+/// Greedy Heaviest Observed SubTree: starting from the genesis block,
+/// repeatedly moves to the child whose subtree holds the most work (lowest
+/// nonce in case of tie) until reaching a leaf. Stale blocks count for
+/// the branch they were mined on. Each update walks the whole store.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Ghost;
+
+impl ForkChoice for Ghost {
+    fn best_tip(&self, store: &BlockStore) -> BlockHash {
+        // Parents come before their children, so going through the blocks
+        // backwards sums the subtrees before their root needs them.
+        let nodes: Vec<&BlockNode> = store.iter().collect();
+        let mut subtree_work: HashMap<BlockHash, u128, BlockIdHasher> = HashMap::default();
+        for node in nodes.iter().rev() {
+            let children_work = node
+                .children()
+                .iter()
+                .fold(0u128, |sum, child| sum.saturating_add(subtree_work[child]));
+            subtree_work.insert(*node.hash(), node.work().saturating_add(children_work));
+        }
+
+        let mut node = store.root();
+        while let Some(child) = store
+            .children(node)
+            .max_by_key(|child| (subtree_work[child.hash()], Reverse(child.block().nonce)))
+        {
+            node = child;
+        }
+        *node.hash()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{Block, DanceMove};
+
+    fn child_of(parent: &BlockHash, nonce: u64, miner: &str) -> Block {
+        Block::new(parent.as_bytes().to_vec(), miner.to_string(), nonce, DanceMove::Y)
+    }
+
+    /// genesis ─┬─ a1 (work 1) ── a2 (work 1) ── a3 (work 1)
+    ///          └─ b1 (work 4) ─┬─ b2 (work 1)
+    ///                          ├─ c2 (work 1)
+    ///                          └─ d2 (work 1)
+    fn create_test_store() -> (BlockStore, [BlockHash; 4]) {
+        let genesis = Block::new(vec![], "Genesis".to_string(), 0, DanceMove::Y);
+        let genesis_hash = genesis.block_hash();
+        let mut store = BlockStore::new(genesis);
+
+        let a1 = store.insert(child_of(&genesis_hash, 1, "a"), 1).unwrap();
+        let a2 = store.insert(child_of(&a1, 2, "a"), 1).unwrap();
+        let a3 = store.insert(child_of(&a2, 3, "a"), 1).unwrap();
+        let b1 = store.insert(child_of(&genesis_hash, 4, "b"), 4).unwrap();
+        let b2 = store.insert(child_of(&b1, 7, "b"), 1).unwrap();
+        store.insert(child_of(&b1, 6, "c"), 1).unwrap();
+        let d2 = store.insert(child_of(&b1, 5, "d"), 1).unwrap();
+        (store, [a3, b1, b2, d2])
+    }
+
+    #[test]
+    fn test_longest_chain() {
+        let (store, [a3, ..]) = create_test_store();
+        assert_eq!(LongestChain.best_tip(&store), a3);
+    }
+
+    #[test]
+    fn test_heaviest_work() {
+        // a3 has a chainwork of 3, all the b1 children have 5. d2 has the lowest nonce.
+        let (store, [_, _, _, d2]) = create_test_store();
+        assert_eq!(HeaviestWork.best_tip(&store), d2);
+    }
+
+    #[test]
+    fn test_ghost() {
+        let (mut store, [a3, b1, _, d2]) = create_test_store();
+        assert_eq!(Ghost.best_tip(&store), d2);
+
+        // The a branch catches up with the b subtree (4 + 3 = 7)
+        let mut tip = a3;
+        for nonce in 10..13 {
+            let inserted = store.insert(child_of(&tip, nonce, "a"), 1).unwrap();
+            assert_eq!(Ghost.update(&store, &d2, &inserted), d2);
+            tip = inserted;
+        }
+        // Tie, a1 has a lower nonce than b1
+        let inserted = store.insert(child_of(&tip, 13, "a"), 1).unwrap();
+        assert_eq!(Ghost.update(&store, &d2, &inserted), inserted);
+
+        // A heavier block on b1 brings the b subtree back ahead, and it's
+        // the heaviest child of b1
+        let e2 = store.insert(child_of(&b1, 20, "e"), 2).unwrap();
+        assert_eq!(Ghost.best_tip(&store), e2);
+    }
+
+    #[test]
+    fn test_incremental_update() {
+        let (store, [a3, _, b2, d2]) = create_test_store();
+        assert_eq!(LongestChain.update(&store, &a3, &b2), a3);
+        assert_eq!(HeaviestWork.update(&store, &a3, &b2), b2);
+        assert_eq!(HeaviestWork.update(&store, &b2, &d2), d2);
+    }
+}
@@ -0,0 +1,228 @@
+use crate::block::Block;
+use crate::chain::InvalidBlock;
+use crate::encoding::{parse_hex, to_hex};
+use crate::transaction::{InvalidTransaction, Transaction};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use rand::RngCore;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// Length in bytes of [`Block::public_key`].
+pub const PUBLIC_KEY_LEN: usize = 32;
+
+/// Length in bytes of [`Block::signature`].
+pub const SIGNATURE_LEN: usize = 64;
+
+/// Ed25519 keypair identifying a miner. The public key goes in the blocks
+/// it mines, and the secret key signs them.
+pub struct MinerKey(SigningKey);
+
+impl MinerKey {
+    /// Creates a new random keypair
+    pub fn generate<R: RngCore>(rng: &mut R) -> Self {
+        let mut seed = [0; 32];
+        rng.fill_bytes(&mut seed);
+        MinerKey::from_seed(seed)
+    }
+
+    /// Creates the keypair derived from a 32 bytes secret seed
+    pub fn from_seed(seed: [u8; 32]) -> Self {
+        MinerKey(SigningKey::from_bytes(&seed))
+    }
+
+    pub fn public_key(&self) -> [u8; PUBLIC_KEY_LEN] {
+        self.0.verifying_key().to_bytes()
+    }
+
+    /// Reads a key written by [`MinerKey::save`]
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut hex = String::new();
+        File::open(path)?.read_to_string(&mut hex)?;
+        let seed = parse_hex(hex.trim())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed key file"))?;
+        Ok(MinerKey::from_seed(seed))
+    }
+
+    /// Writes the secret seed, in hex, to a new file only readable by its
+    /// owner. Fails if the file already exists.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(path)?;
+        writeln!(file, "{}", to_hex(self.0.as_bytes()))
+    }
+}
+
+impl fmt::Debug for MinerKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print the secret key
+        write!(f, "MinerKey({})", to_hex(&self.public_key()))
+    }
+}
+
+impl Block {
+    /// Signs the header of the block. The public key of `key` must already
+    /// be in the block: the proof of work covers it, so it's set before
+    /// mining, and the signature after.
+    pub fn sign(&mut self, key: &MinerKey) {
+        debug_assert_eq!(self.public_key, key.public_key());
+        self.signature = key.0.sign(&self.encode_header()).to_bytes().to_vec();
+    }
+
+    /// Checks that the header is signed by the key of the block
+    pub fn verify_signature(&self) -> Result<(), InvalidBlock> {
+        let public_key = verifying_key(&self.public_key).ok_or(InvalidBlock::MalformedPublicKey)?;
+        if !verify(&public_key, &self.encode_header(), &self.signature) {
+            return Err(InvalidBlock::InvalidSignature);
+        }
+        Ok(())
+    }
+
+    /// Checks the signature of the header and of every transaction
+    pub fn verify_signatures(&self) -> Result<(), InvalidBlock> {
+        self.verify_signature()?;
+        self.transactions
+            .iter()
+            .try_for_each(Transaction::verify_signature)
+            .map_err(InvalidBlock::InvalidTransaction)
+    }
+
+    /// Alias of the miner followed by the start of its public key, to print
+    /// the block
+    pub fn author(&self) -> String {
+        let key: String = to_hex(&self.public_key).chars().take(8).collect();
+        match (self.miner.is_empty(), key.is_empty()) {
+            (_, true) => self.miner.clone(),
+            (true, false) => key,
+            (false, false) => format!("{} [{}]", self.miner, key),
+        }
+    }
+}
+
+impl Transaction {
+    /// Signs the transaction. `key` must be the key of the sender.
+    pub fn sign(&mut self, key: &MinerKey) {
+        debug_assert_eq!(self.from, key.public_key());
+        self.signature = key.0.sign(&self.encode_unsigned()).to_bytes().to_vec();
+    }
+
+    /// Checks that the transaction is signed by its sender
+    pub fn verify_signature(&self) -> Result<(), InvalidTransaction> {
+        let public_key = verifying_key(&self.from).ok_or(InvalidTransaction::MalformedKey)?;
+        if !verify(&public_key, &self.encode_unsigned(), &self.signature) {
+            return Err(InvalidTransaction::InvalidSignature);
+        }
+        Ok(())
+    }
+}
+
+fn verifying_key(public_key: &[u8]) -> Option<VerifyingKey> {
+    let public_key = <[u8; PUBLIC_KEY_LEN]>::try_from(public_key).ok()?;
+    VerifyingKey::from_bytes(&public_key).ok()
+}
+
+fn verify(public_key: &VerifyingKey, message: &[u8], signature: &[u8]) -> bool {
+    <[u8; SIGNATURE_LEN]>::try_from(signature).is_ok_and(|signature| {
+        public_key
+            .verify_strict(message, &Signature::from_bytes(&signature))
+            .is_ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::DanceMove;
+    use crate::transaction::Payload;
+
+    fn signed_block(key: &MinerKey) -> Block {
+        let mut block = Block::new(vec![0xab; 32], "miner1".to_string(), 42, DanceMove::A);
+        block.public_key = key.public_key().to_vec();
+        block.sign(key);
+        block
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = MinerKey::from_seed([1; 32]);
+        let block = signed_block(&key);
+        assert_eq!(block.signature.len(), SIGNATURE_LEN);
+        assert_eq!(block.verify_signature(), Ok(()));
+
+        // Any change of the header breaks the signature
+        let mut tampered = block.clone();
+        tampered.nonce += 1;
+        assert_eq!(tampered.verify_signature(), Err(InvalidBlock::InvalidSignature));
+        let mut tampered = block.clone();
+        tampered.miner = "miner2".to_string();
+        assert_eq!(tampered.verify_signature(), Err(InvalidBlock::InvalidSignature));
+
+        // Mining under someone else's key needs their secret key
+        let mut stolen = signed_block(&MinerKey::from_seed([2; 32]));
+        stolen.public_key = key.public_key().to_vec();
+        assert_eq!(stolen.verify_signature(), Err(InvalidBlock::InvalidSignature));
+
+        let mut unsigned = block.clone();
+        unsigned.signature.clear();
+        assert_eq!(unsigned.verify_signature(), Err(InvalidBlock::InvalidSignature));
+        let mut malformed = block;
+        malformed.public_key.truncate(31);
+        assert_eq!(malformed.verify_signature(), Err(InvalidBlock::MalformedPublicKey));
+    }
+
+    #[test]
+    fn test_transaction_signatures() {
+        let key = MinerKey::from_seed([1; 32]);
+        let mut tx = Transaction::new(
+            key.public_key().to_vec(),
+            0,
+            Payload::Transfer { to: vec![2; 32], amount: 10 },
+        );
+        tx.sign(&key);
+        assert_eq!(tx.verify_signature(), Ok(()));
+
+        let mut block = signed_block(&key);
+        block.set_transactions(vec![tx.clone()]);
+        block.sign(&key);
+        assert_eq!(block.verify_signatures(), Ok(()));
+
+        // Spending someone else's coins
+        tx.payload = Payload::Transfer { to: vec![2; 32], amount: 1000 };
+        assert_eq!(tx.verify_signature(), Err(InvalidTransaction::InvalidSignature));
+        block.set_transactions(vec![tx]);
+        block.sign(&key);
+        assert_eq!(
+            block.verify_signatures(),
+            Err(InvalidBlock::InvalidTransaction(InvalidTransaction::InvalidSignature))
+        );
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("miner-key-test-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let key = MinerKey::generate(&mut rand::rng());
+        key.save(&path).unwrap();
+        assert_eq!(MinerKey::load(&path).unwrap().public_key(), key.public_key());
+        // Never overwrite a key
+        assert!(MinerKey::generate(&mut rand::rng()).save(&path).is_err());
+        assert_eq!(MinerKey::load(&path).unwrap().public_key(), key.public_key());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_author() {
+        let key = MinerKey::from_seed([1; 32]);
+        let block = signed_block(&key);
+        let prefix = &to_hex(&key.public_key())[..8];
+        assert_eq!(block.author(), format!("miner1 [{}]", prefix));
+        let anonymous = Block { miner: String::new(), ..block };
+        assert_eq!(anonymous.author(), prefix);
+    }
+}
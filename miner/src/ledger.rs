@@ -0,0 +1,235 @@
+use crate::block::Block;
+use crate::chain::InvalidBlock;
+use crate::transaction::{InvalidTransaction, Payload, Transaction};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// State of a miner key.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Account {
+    /// Coins owned by the key
+    pub balance: u64,
+    /// Number of transactions sent by the key, the nonce of the next one
+    pub nonce: u64,
+}
+
+/// Account of a key, with the latest alias it mined a block of the best
+/// chain under, as listed by [`crate::chain::Blockchain::balances`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Balance {
+    /// Public key, in hex
+    pub public_key: String,
+    /// Empty if the key didn't mine under an alias
+    pub miner: String,
+    pub balance: u64,
+    pub nonce: u64,
+}
+
+/// Accounts of the miner keys after a sequence of blocks: each block
+/// credits its miner the block reward, then its transactions are applied
+/// in order. Blocks can be undone, so the ledger can follow the tip when
+/// it switches branches.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ledger {
+    /// Only the accounts that aren't [`Account::default`]
+    accounts: HashMap<Vec<u8>, Account>,
+}
+
+impl Ledger {
+    /// Ledger after the genesis block. Its transactions are ignored, it
+    /// only credits its reward.
+    pub fn genesis(block: &Block, reward: u64) -> Self {
+        let mut ledger = Ledger::default();
+        ledger.update(&block.public_key, |account| account.balance = reward);
+        ledger
+    }
+
+    /// Get the account of a key
+    pub fn account(&self, key: &[u8]) -> Account {
+        self.accounts.get(key).copied().unwrap_or_default()
+    }
+
+    /// Iterate over the accounts with coins or transactions
+    pub fn accounts(&self) -> impl Iterator<Item = (&[u8], &Account)> {
+        self.accounts.iter().map(|(key, account)| (key.as_slice(), account))
+    }
+
+    /// Checks that `transaction` can be applied: its nonce is the next one
+    /// of the sender, who can afford the transfer, and the balance of the
+    /// receiver doesn't overflow
+    pub fn check_transaction(&self, transaction: &Transaction) -> Result<(), InvalidTransaction> {
+        let sender = self.account(&transaction.from);
+        if transaction.nonce != sender.nonce {
+            return Err(InvalidTransaction::WrongNonce);
+        }
+        if let Payload::Transfer { to, amount } = &transaction.payload {
+            if *amount > sender.balance {
+                return Err(InvalidTransaction::InsufficientBalance);
+            }
+            // The coins sent to oneself are taken first
+            if *to != transaction.from && self.account(to).balance.checked_add(*amount).is_none() {
+                return Err(InvalidTransaction::BalanceOverflow);
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies a transaction, if [`Ledger::check_transaction`] accepts it
+    pub fn apply_transaction(&mut self, transaction: &Transaction) -> Result<(), InvalidTransaction> {
+        self.check_transaction(transaction)?;
+        self.update(&transaction.from, |sender| {
+            sender.nonce += 1;
+            if let Payload::Transfer { amount, .. } = transaction.payload {
+                sender.balance -= amount;
+            }
+        });
+        if let Payload::Transfer { to, amount } = &transaction.payload {
+            self.update(to, |receiver| receiver.balance += amount);
+        }
+        Ok(())
+    }
+
+    /// Credits the miner of `block` and applies its transactions. If the
+    /// reward or a transaction is rejected, the ledger is left unchanged.
+    pub fn apply_block(&mut self, block: &Block, reward: u64) -> Result<(), InvalidBlock> {
+        let balance = self.account(&block.public_key).balance.checked_add(reward);
+        let balance = balance.ok_or(InvalidBlock::BalanceOverflow)?;
+        self.update(&block.public_key, |miner| miner.balance = balance);
+        for (applied, transaction) in block.transactions.iter().enumerate() {
+            if let Err(reason) = self.apply_transaction(transaction) {
+                self.undo_transactions(&block.transactions[..applied]);
+                self.update(&block.public_key, |miner| miner.balance -= reward);
+                return Err(InvalidBlock::InvalidTransaction(reason));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reverts [`Ledger::apply_block`]. `block` must be the last block
+    /// applied.
+    pub fn undo_block(&mut self, block: &Block, reward: u64) {
+        self.undo_transactions(&block.transactions);
+        self.update(&block.public_key, |miner| miner.balance -= reward);
+    }
+
+    fn undo_transactions(&mut self, transactions: &[Transaction]) {
+        for transaction in transactions.iter().rev() {
+            if let Payload::Transfer { to, amount } = &transaction.payload {
+                self.update(to, |receiver| receiver.balance -= amount);
+            }
+            self.update(&transaction.from, |sender| {
+                sender.nonce -= 1;
+                if let Payload::Transfer { amount, .. } = transaction.payload {
+                    sender.balance += amount;
+                }
+            });
+        }
+    }
+
+    /// Changes an account, dropping it if it goes back to its default
+    fn update(&mut self, key: &[u8], change: impl FnOnce(&mut Account)) {
+        let mut account = self.account(key);
+        change(&mut account);
+        if account == Account::default() {
+            self.accounts.remove(key);
+        } else {
+            self.accounts.insert(key.to_vec(), account);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::DanceMove;
+
+    const REWARD: u64 = 50;
+
+    fn block(miner: u8, transactions: Vec<Transaction>) -> Block {
+        let mut block = Block::new(vec![0; 32], String::new(), 0, DanceMove::Y);
+        block.public_key = vec![miner; 32];
+        block.set_transactions(transactions);
+        block
+    }
+
+    fn transfer(from: u8, nonce: u64, to: u8, amount: u64) -> Transaction {
+        let payload = Payload::Transfer { to: vec![to; 32], amount };
+        Transaction::new(vec![from; 32], nonce, payload)
+    }
+
+    #[test]
+    fn test_apply_and_undo() {
+        let genesis = Ledger::genesis(&block(1, vec![transfer(1, 0, 2, 10)]), REWARD);
+        assert_eq!(genesis.account(&[1; 32]), Account { balance: REWARD, nonce: 0 });
+        assert_eq!(genesis.account(&[2; 32]), Account::default());
+
+        let mut ledger = genesis.clone();
+        let data = Transaction::new(vec![2; 32], 1, Payload::Data(b"record".to_vec()));
+        // The miner can spend the reward of the block in the block
+        let block1 = block(2, vec![transfer(1, 0, 2, 30), transfer(2, 0, 3, 60), data]);
+        ledger.apply_block(&block1, REWARD).unwrap();
+        assert_eq!(ledger.account(&[1; 32]), Account { balance: 20, nonce: 1 });
+        assert_eq!(ledger.account(&[2; 32]), Account { balance: 20, nonce: 2 });
+        assert_eq!(ledger.account(&[3; 32]), Account { balance: 60, nonce: 0 });
+        assert_eq!(ledger.accounts().count(), 3);
+
+        ledger.undo_block(&block1, REWARD);
+        assert_eq!(ledger, genesis);
+    }
+
+    #[test]
+    fn test_rejected_transactions() {
+        let mut ledger = Ledger::genesis(&block(1, vec![]), REWARD);
+        assert_eq!(
+            ledger.check_transaction(&transfer(1, 0, 2, REWARD + 1)),
+            Err(InvalidTransaction::InsufficientBalance)
+        );
+        assert_eq!(
+            ledger.check_transaction(&transfer(1, 1, 2, 1)),
+            Err(InvalidTransaction::WrongNonce)
+        );
+        assert_eq!(ledger.check_transaction(&transfer(1, 0, 2, REWARD)), Ok(()));
+
+        // A replayed transaction is rejected, and so is the whole block
+        let before = ledger.clone();
+        let replay = block(3, vec![transfer(1, 0, 2, 10), transfer(1, 0, 2, 10)]);
+        assert_eq!(
+            ledger.apply_block(&replay, REWARD),
+            Err(InvalidBlock::InvalidTransaction(InvalidTransaction::WrongNonce))
+        );
+        assert_eq!(ledger, before);
+    }
+
+    #[test]
+    fn test_balance_overflow() {
+        let reward = u64::MAX - 10;
+        let genesis = Ledger::genesis(&block(1, vec![]), reward);
+
+        // The reward of a second block would overflow, nothing changes
+        let mut ledger = genesis.clone();
+        assert_eq!(ledger.apply_block(&block(1, vec![]), reward), Err(InvalidBlock::BalanceOverflow));
+        assert_eq!(ledger, genesis);
+
+        // So would the transfer to a key holding a reward
+        ledger.apply_block(&block(2, vec![]), reward).unwrap();
+        let after = ledger.clone();
+        assert_eq!(
+            ledger.check_transaction(&transfer(1, 0, 2, 11)),
+            Err(InvalidTransaction::BalanceOverflow)
+        );
+        let overflowing = block(3, vec![transfer(1, 0, 2, 10), transfer(1, 1, 2, 1)]);
+        assert_eq!(
+            ledger.apply_block(&overflowing, reward),
+            Err(InvalidBlock::InvalidTransaction(InvalidTransaction::BalanceOverflow))
+        );
+        assert_eq!(ledger, after);
+
+        // Up to the limit, undoing restores the balances exactly
+        let full = block(3, vec![transfer(1, 0, 2, 10), transfer(1, 1, 1, 5)]);
+        ledger.apply_block(&full, reward).unwrap();
+        assert_eq!(ledger.account(&[2; 32]).balance, u64::MAX);
+        assert_eq!(ledger.account(&[1; 32]), Account { balance: reward - 10, nonce: 2 });
+        ledger.undo_block(&full, reward);
+        assert_eq!(ledger, after);
+    }
+}
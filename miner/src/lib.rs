@@ -1,2 +1,15 @@
 pub mod block;
+pub mod blockstore;
+pub mod chain;
+pub mod encoding;
+pub mod forkchoice;
+pub mod identity;
+pub mod ledger;
+pub mod lightclient;
+pub mod mempool;
+pub mod params;
 pub mod simpletree;
+pub mod stats;
+pub mod sync;
+pub mod target;
+pub mod transaction;
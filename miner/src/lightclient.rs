@@ -0,0 +1,352 @@
+use crate::block::{merkle_proof, timestamp_now, Block, BlockHash, MerkleProof};
+use crate::chain::{check_header, InvalidBlock, MAX_FUTURE_DRIFT, MEDIAN_TIME_SPAN};
+use crate::params::ChainParams;
+use crate::transaction::{Transaction, TxId};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Proof that a transaction is in a block, as served by `GET /proof/<txid>`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct InclusionProof {
+    /// Hash of the block holding the transaction
+    pub block_hash: BlockHash,
+    pub height: u64,
+    pub transaction: Transaction,
+    /// Path from the transaction to the Merkle root of the block
+    pub merkle_proof: MerkleProof,
+}
+
+impl InclusionProof {
+    /// Proof that `block.transactions[index]` is in `block`, `None` if
+    /// `index` is out of bounds
+    pub fn new(block: &Block, index: usize) -> Option<Self> {
+        Some(InclusionProof {
+            block_hash: block.block_hash(),
+            height: block.height,
+            transaction: block.transactions.get(index)?.clone(),
+            merkle_proof: merkle_proof(&block.transactions, index)?,
+        })
+    }
+}
+
+/// Why a header can't extend a [`LightClient`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidHeader {
+    /// The header breaks a rule of the chain.
+    Invalid(InvalidBlock),
+    /// The hash of the header doesn't meet its target.
+    InvalidProofOfWork,
+    /// The header doesn't follow the last header, or the first header
+    /// isn't a genesis block.
+    UnknownParent,
+}
+
+impl InvalidHeader {
+    /// Short machine readable identifier of the reason
+    pub fn code(&self) -> &'static str {
+        match self {
+            InvalidHeader::Invalid(reason) => reason.code(),
+            InvalidHeader::InvalidProofOfWork => "invalid_proof_of_work",
+            InvalidHeader::UnknownParent => "unknown_parent",
+        }
+    }
+}
+
+impl fmt::Display for InvalidHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidHeader::Invalid(reason) => reason.fmt(f),
+            InvalidHeader::InvalidProofOfWork => write!(f, "the proof of work is invalid"),
+            InvalidHeader::UnknownParent => {
+                write!(f, "the header doesn't follow the last known header")
+            }
+        }
+    }
+}
+
+impl std::error::Error for InvalidHeader {}
+
+impl From<InvalidBlock> for InvalidHeader {
+    fn from(reason: InvalidBlock) -> Self {
+        InvalidHeader::Invalid(reason)
+    }
+}
+
+/// Why an [`InclusionProof`] isn't accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidProof {
+    /// The proof is about another transaction.
+    WrongTransaction,
+    /// The block isn't in the header chain.
+    UnknownBlock,
+    /// The Merkle proof doesn't lead to the Merkle root of the block.
+    MerkleRootMismatch,
+}
+
+impl InvalidProof {
+    /// Short machine readable identifier of the reason
+    pub fn code(&self) -> &'static str {
+        match self {
+            InvalidProof::WrongTransaction => "wrong_transaction",
+            InvalidProof::UnknownBlock => "unknown_block",
+            InvalidProof::MerkleRootMismatch => "merkle_root_mismatch",
+        }
+    }
+}
+
+impl fmt::Display for InvalidProof {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidProof::WrongTransaction => write!(f, "the proof is about another transaction"),
+            InvalidProof::UnknownBlock => write!(f, "the block isn't in the header chain"),
+            InvalidProof::MerkleRootMismatch => {
+                write!(f, "the proof doesn't lead to the Merkle root of the block")
+            }
+        }
+    }
+}
+
+impl std::error::Error for InvalidProof {}
+
+/// Chain of block headers, checked without the transactions: proof of
+/// work, signatures, heights, timestamps and difficulty. Transactions are
+/// then checked to be in the chain with an [`InclusionProof`].
+///
+/// The headers form a single chain from the genesis block, like the best
+/// chain served by `GET /headers`.
+#[derive(Debug, Clone)]
+pub struct LightClient {
+    params: ChainParams,
+    /// Header at each height
+    headers: Vec<Block>,
+    hashes: Vec<BlockHash>,
+}
+
+impl LightClient {
+    /// Creates a client without any header, for a chain with these
+    /// consensus parameters
+    pub fn new(params: ChainParams) -> Self {
+        LightClient {
+            params,
+            headers: Vec::new(),
+            hashes: Vec::new(),
+        }
+    }
+
+    /// Checks a header and appends it to the chain. The transactions of
+    /// `header`, if any, are dropped without being checked.
+    pub fn add_header(&mut self, header: Block) -> Result<(), InvalidHeader> {
+        check_header(&header)?;
+        // check_header made sure the target is valid
+        let target = header.target().unwrap();
+        if !header.pow_check(&header.pow_hash(self.params.pow.proof_of_work()), &target) {
+            return Err(InvalidHeader::InvalidProofOfWork);
+        }
+        header.verify_signature()?;
+
+        match self.hashes.last() {
+            None if !header.parent_hash.is_empty() => return Err(InvalidHeader::UnknownParent),
+            Some(tip) if header.parent() != Some(*tip) => return Err(InvalidHeader::UnknownParent),
+            _ => {}
+        }
+        if header.height != self.headers.len() as u64 {
+            return Err(InvalidBlock::WrongHeight.into());
+        }
+        if !self.headers.is_empty() && header.timestamp <= self.median_time_past() {
+            return Err(InvalidBlock::TimestampTooOld.into());
+        }
+        if header.timestamp > timestamp_now().saturating_add(MAX_FUTURE_DRIFT) {
+            return Err(InvalidBlock::TimestampTooFarInFuture.into());
+        }
+        if header.bits != self.next_bits() {
+            return Err(InvalidBlock::WrongDifficulty.into());
+        }
+
+        self.hashes.push(header.block_hash());
+        self.headers.push(header.header());
+        Ok(())
+    }
+
+    /// Checks that the transaction `txid` is in a block of the header
+    /// chain. Returns the number of confirmations of the transaction: 1 if
+    /// it's in the last header, 2 in the one before...
+    pub fn verify(&self, txid: &TxId, proof: &InclusionProof) -> Result<u64, InvalidProof> {
+        if proof.transaction.txid() != *txid {
+            return Err(InvalidProof::WrongTransaction);
+        }
+        let header = usize::try_from(proof.height)
+            .ok()
+            .filter(|&height| self.hashes.get(height) == Some(&proof.block_hash))
+            .map(|height| &self.headers[height])
+            .ok_or(InvalidProof::UnknownBlock)?;
+        if !proof.merkle_proof.verify(&proof.transaction, &header.merkle_root) {
+            return Err(InvalidProof::MerkleRootMismatch);
+        }
+        Ok(self.headers.len() as u64 - proof.height)
+    }
+
+    /// Get the last header
+    pub fn tip(&self) -> Option<&Block> {
+        self.headers.last()
+    }
+
+    /// Get the header at `height`
+    pub fn get(&self, height: u64) -> Option<&Block> {
+        self.headers.get(usize::try_from(height).ok()?)
+    }
+
+    /// Number of headers, genesis included
+    pub fn len(&self) -> usize {
+        self.headers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+
+    /// Compact target the next header must have, see
+    /// [`crate::chain::Blockchain::next_bits`]
+    pub fn next_bits(&self) -> u32 {
+        let Some(parent) = self.headers.last() else {
+            return self.params.initial_bits();
+        };
+        if parent.height == 0 {
+            return self.params.initial_bits();
+        }
+        if !self.params.is_retarget_height(parent.height + 1) {
+            return parent.bits;
+        }
+
+        // The first window starts at the genesis block
+        let window = usize::try_from(self.params.retarget_window).unwrap_or(usize::MAX);
+        let first = &self.headers[self.headers.len().saturating_sub(window.saturating_add(1))];
+        let elapsed = parent.timestamp.saturating_sub(first.timestamp);
+        self.params.retarget(parent.bits, parent.height - first.height, elapsed)
+    }
+
+    /// Median timestamp of the last [`MEDIAN_TIME_SPAN`] headers
+    fn median_time_past(&self) -> u64 {
+        let mut timestamps: Vec<u64> = self
+            .headers
+            .iter()
+            .rev()
+            .take(MEDIAN_TIME_SPAN)
+            .map(|header| header.timestamp)
+            .collect();
+        timestamps.sort_unstable();
+        timestamps[timestamps.len() / 2]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::DanceMove;
+    use crate::identity::MinerKey;
+    use crate::transaction::Payload;
+
+    fn params() -> ChainParams {
+        ChainParams {
+            initial_difficulty: 4,
+            retarget_window: 3,
+            ..ChainParams::default()
+        }
+    }
+
+    fn mine(mut block: Block, key: &MinerKey, params: &ChainParams) -> Block {
+        block.public_key = key.public_key().to_vec();
+        let target = block.target().unwrap();
+        block
+            .solve_block(&mut rand::rng(), params.pow.proof_of_work(), &target, None)
+            .unwrap();
+        block.sign(key);
+        block
+    }
+
+    /// A chain of `len` blocks, each one with a transaction of its miner
+    fn chain(len: u64, key: &MinerKey, params: &ChainParams) -> Vec<Block> {
+        let mut client = LightClient::new(*params);
+        let genesis = Block {
+            bits: params.initial_bits(),
+            ..Block::new(vec![], "Genesis".to_string(), 0, DanceMove::Y)
+        };
+        let mut blocks = vec![mine(genesis, key, params)];
+        client.add_header(blocks[0].clone()).unwrap();
+        for nonce in 0..len - 1 {
+            let parent = blocks.last().unwrap();
+            let mut block = Block::new_child(
+                parent,
+                "miner1".to_string(),
+                DanceMove::M,
+                parent.timestamp + 1000,
+                client.next_bits(),
+            );
+            let data = Payload::Data(format!("block {}", block.height).into_bytes());
+            let mut transaction = Transaction::new(key.public_key().to_vec(), nonce, data);
+            transaction.sign(key);
+            block.set_transactions(vec![transaction]);
+            let block = mine(block, key, params);
+            client.add_header(block.clone()).unwrap();
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    #[test]
+    fn test_headers() {
+        let (params, key) = (params(), MinerKey::from_seed([1; 32]));
+        let blocks = chain(5, &key, &params);
+        let mut client = LightClient::new(params);
+        assert_eq!(client.add_header(blocks[1].clone()), Err(InvalidHeader::UnknownParent));
+        for block in &blocks[..3] {
+            client.add_header(block.header()).unwrap();
+        }
+        assert_eq!(client.tip(), Some(&blocks[2].header()));
+
+        // Changing the header needs a new proof of work
+        let mut forged = blocks[3].clone();
+        let target = forged.target().unwrap();
+        while forged.pow_check(&forged.pow_hash(params.pow.proof_of_work()), &target) {
+            forged.nonce += 1;
+        }
+        forged.sign(&key);
+        assert_eq!(client.add_header(forged), Err(InvalidHeader::InvalidProofOfWork));
+        let mut unsigned = blocks[3].clone();
+        unsigned.signature.clear();
+        assert_eq!(
+            client.add_header(unsigned),
+            Err(InvalidHeader::Invalid(InvalidBlock::InvalidSignature))
+        );
+        assert_eq!(client.add_header(blocks[4].clone()), Err(InvalidHeader::UnknownParent));
+        client.add_header(blocks[3].clone()).unwrap();
+        client.add_header(blocks[4].clone()).unwrap();
+        assert_eq!(client.len(), 5);
+        assert!(client.get(4).unwrap().transactions.is_empty());
+    }
+
+    #[test]
+    fn test_verify() {
+        let (params, key) = (params(), MinerKey::from_seed([1; 32]));
+        let blocks = chain(4, &key, &params);
+        let mut client = LightClient::new(params);
+        for block in &blocks {
+            client.add_header(block.header()).unwrap();
+        }
+
+        let transaction = &blocks[2].transactions[0];
+        let proof = InclusionProof::new(&blocks[2], 0).unwrap();
+        assert_eq!(client.verify(&transaction.txid(), &proof), Ok(2));
+        let other = blocks[1].transactions[0].txid();
+        assert_eq!(client.verify(&other, &proof), Err(InvalidProof::WrongTransaction));
+
+        let mut tampered = proof.clone();
+        tampered.transaction.nonce += 1;
+        assert_eq!(
+            client.verify(&tampered.transaction.txid(), &tampered),
+            Err(InvalidProof::MerkleRootMismatch)
+        );
+        let moved = InclusionProof { height: 1, ..proof.clone() };
+        assert_eq!(client.verify(&transaction.txid(), &moved), Err(InvalidProof::UnknownBlock));
+        assert_eq!(InclusionProof::new(&blocks[2], 1), None);
+    }
+}
@@ -0,0 +1,251 @@
+use crate::block::{BlockHash, MAX_BLOCK_TRANSACTIONS};
+use crate::chain::Blockchain;
+use crate::ledger::Ledger;
+use crate::transaction::{InvalidTransaction, Transaction, TxId};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+
+/// Maximum number of transactions waiting in a [`Mempool`].
+pub const MAX_MEMPOOL_SIZE: usize = 10_000;
+
+/// Why a transaction wasn't added to the mempool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectedTransaction {
+    /// The transaction is invalid, or can't follow the tip and the other
+    /// transactions of the mempool.
+    Invalid(InvalidTransaction),
+    /// The transaction is already in the mempool.
+    Duplicate,
+    /// The mempool holds [`MAX_MEMPOOL_SIZE`] transactions.
+    Full,
+}
+
+impl RejectedTransaction {
+    /// Short machine readable identifier of the reason
+    pub fn code(&self) -> &'static str {
+        match self {
+            RejectedTransaction::Invalid(reason) => reason.code(),
+            RejectedTransaction::Duplicate => "duplicate_transaction",
+            RejectedTransaction::Full => "mempool_full",
+        }
+    }
+}
+
+impl fmt::Display for RejectedTransaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectedTransaction::Invalid(reason) => reason.fmt(f),
+            RejectedTransaction::Duplicate => write!(f, "the transaction is already pending"),
+            RejectedTransaction::Full => {
+                write!(f, "the mempool already holds {} transactions", MAX_MEMPOOL_SIZE)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RejectedTransaction {}
+
+/// What to mine next, as served by `GET /template`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BlockTemplate {
+    /// Tip the transactions were checked against
+    pub parent_hash: BlockHash,
+    pub height: u64,
+    pub bits: u32,
+    /// Body of the next block, in order
+    pub transactions: Vec<Transaction>,
+}
+
+/// Transactions waiting to be mined, in the order they were received. They
+/// can all be applied, in this order, after the tip of the chain.
+#[derive(Debug, Clone, Default)]
+pub struct Mempool {
+    transactions: Vec<Transaction>,
+    txids: HashSet<TxId>,
+    /// Accounts after the tip and the transactions of the mempool
+    ledger: Ledger,
+}
+
+impl Mempool {
+    /// Creates an empty mempool for the tip of `chain`
+    pub fn new(chain: &Blockchain) -> Self {
+        Mempool {
+            ledger: chain.ledger().clone(),
+            ..Mempool::default()
+        }
+    }
+
+    /// Adds a transaction if it's well formed, signed by its sender, and
+    /// can be applied after the tip and the other pending transactions
+    pub fn add(&mut self, transaction: Transaction) -> Result<(), RejectedTransaction> {
+        let txid = transaction.txid();
+        if self.txids.contains(&txid) {
+            return Err(RejectedTransaction::Duplicate);
+        }
+        if self.transactions.len() >= MAX_MEMPOOL_SIZE {
+            return Err(RejectedTransaction::Full);
+        }
+        transaction
+            .check_structure()
+            .and_then(|()| transaction.verify_signature())
+            .and_then(|()| self.ledger.apply_transaction(&transaction))
+            .map_err(RejectedTransaction::Invalid)?;
+
+        self.txids.insert(txid);
+        self.transactions.push(transaction);
+        Ok(())
+    }
+
+    /// Follows a new tip of `chain`: the transactions that were mined, or
+    /// that can no longer be applied, are evicted. Returns the number of
+    /// evicted transactions.
+    pub fn update(&mut self, chain: &Blockchain) -> usize {
+        let pending = std::mem::take(&mut self.transactions);
+        let count = pending.len();
+        self.txids.clear();
+        self.ledger = chain.ledger().clone();
+
+        for transaction in pending {
+            if self.ledger.apply_transaction(&transaction).is_ok() {
+                self.txids.insert(transaction.txid());
+                self.transactions.push(transaction);
+            }
+        }
+        count - self.transactions.len()
+    }
+
+    /// Returns true if the transaction is pending
+    pub fn contains(&self, txid: &TxId) -> bool {
+        self.txids.contains(txid)
+    }
+
+    /// Iterate over the pending transactions, in order
+    pub fn iter(&self) -> impl Iterator<Item = &Transaction> {
+        self.transactions.iter()
+    }
+
+    /// Number of pending transactions
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    /// Next block to mine on the tip of `chain`, with the oldest pending
+    /// transactions. The mempool must follow the tip, see
+    /// [`Mempool::update`].
+    pub fn template(&self, chain: &Blockchain) -> BlockTemplate {
+        let tip = chain.tip();
+        BlockTemplate {
+            parent_hash: *tip.hash(),
+            height: tip.height() + 1,
+            bits: chain.next_bits(tip),
+            transactions: self.transactions.iter().take(MAX_BLOCK_TRANSACTIONS).cloned().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{Block, DanceMove};
+    use crate::identity::MinerKey;
+    use crate::params::ChainParams;
+    use crate::transaction::Payload;
+
+    fn key(seed: u8) -> MinerKey {
+        MinerKey::from_seed([seed; 32])
+    }
+
+    fn pay(from: &MinerKey, nonce: u64, to: &MinerKey, amount: u64) -> Transaction {
+        let payload = Payload::Transfer { to: to.public_key().to_vec(), amount };
+        let mut transaction = Transaction::new(from.public_key().to_vec(), nonce, payload);
+        transaction.sign(from);
+        transaction
+    }
+
+    fn mined_by(chain: &Blockchain, miner: &MinerKey, transactions: Vec<Transaction>) -> Block {
+        let tip = chain.tip();
+        let mut block = Block::new_child(
+            tip.block(),
+            String::new(),
+            DanceMove::Y,
+            tip.block().timestamp + 1,
+            chain.next_bits(tip),
+        );
+        block.public_key = miner.public_key().to_vec();
+        block.set_transactions(transactions);
+        block
+    }
+
+    #[test]
+    fn test_add() {
+        let (alice, bob) = (key(1), key(2));
+        let mut genesis = Block::new(vec![], "Genesis".to_string(), 0, DanceMove::Y);
+        genesis.public_key = alice.public_key().to_vec();
+        let chain = Blockchain::new(genesis);
+        let reward = chain.params().block_reward;
+        let mut mempool = Mempool::new(&chain);
+
+        assert_eq!(mempool.add(pay(&alice, 0, &bob, 20)), Ok(()));
+        assert_eq!(mempool.add(pay(&alice, 0, &bob, 20)), Err(RejectedTransaction::Duplicate));
+        // Pending transactions are taken into account
+        assert_eq!(
+            mempool.add(pay(&alice, 1, &bob, reward)),
+            Err(RejectedTransaction::Invalid(InvalidTransaction::InsufficientBalance))
+        );
+        assert_eq!(mempool.add(pay(&alice, 1, &bob, reward - 20)), Ok(()));
+        assert_eq!(mempool.add(pay(&bob, 0, &alice, 30)), Ok(()));
+        assert_eq!(
+            mempool.add(pay(&bob, 2, &alice, 1)),
+            Err(RejectedTransaction::Invalid(InvalidTransaction::WrongNonce))
+        );
+        let mut forged = pay(&bob, 1, &alice, 1);
+        forged.from = alice.public_key().to_vec();
+        forged.nonce = 2;
+        assert_eq!(
+            mempool.add(forged),
+            Err(RejectedTransaction::Invalid(InvalidTransaction::InvalidSignature))
+        );
+        assert_eq!(mempool.len(), 3);
+
+        let template = mempool.template(&chain);
+        assert_eq!(template.parent_hash, *chain.tip().hash());
+        assert_eq!(template.height, 1);
+        assert_eq!(template.transactions, mempool.iter().cloned().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_update_evicts() {
+        let (alice, bob, carol) = (key(1), key(2), key(3));
+        let mut genesis = Block::new(vec![], "Genesis".to_string(), 0, DanceMove::Y);
+        genesis.public_key = alice.public_key().to_vec();
+        let mut chain = Blockchain::new(genesis).with_params(ChainParams {
+            block_reward: 100,
+            ..ChainParams::default()
+        });
+        let mut mempool = Mempool::new(&chain);
+
+        let mined = pay(&alice, 0, &bob, 10);
+        let conflicting = pay(&alice, 1, &carol, 90);
+        let pending = pay(&alice, 1, &bob, 10);
+        mempool.add(mined.clone()).unwrap();
+        mempool.add(pending.clone()).unwrap();
+
+        // A block spends most of the coins of alice in another way
+        let block = mined_by(&chain, &carol, vec![mined.clone(), conflicting]);
+        assert!(matches!(chain.add_block(block), crate::chain::AddBlockOutcome::Inserted { .. }));
+        assert_eq!(mempool.update(&chain), 2);
+        assert!(mempool.is_empty());
+
+        mempool.add(pay(&bob, 0, &carol, 5)).unwrap();
+        let block = mined_by(&chain, &carol, vec![]);
+        chain.add_block(block);
+        assert_eq!(mempool.update(&chain), 0);
+        assert_eq!(mempool.len(), 1);
+        assert!(!mempool.contains(&pending.txid()));
+    }
+}
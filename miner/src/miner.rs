@@ -1,186 +1,624 @@
-use block::Block;
-use block::BlockHashSet;
-use block::DanceMove;
-use block::DIFFICULTY;
-use clap::{Parser, Subcommand};
-use network::NetworkConnector;
-use simpletree::TreeNode;
-use std::fmt;
-use std::sync::mpsc;
+use clap::{Parser, Subcommand, ValueEnum};
+use miner::block::Block;
+use miner::block::DanceMove;
+use miner::block::timestamp_now;
+use miner::chain::{AddBlockOutcome, Blockchain};
+use miner::forkchoice::{ForkChoice, Ghost, HeaviestWork, LongestChain};
+use miner::encoding::{parse_hex, to_hex};
+use miner::identity::MinerKey;
+use miner::lightclient::LightClient;
+use miner::params::{ChainParams, PowAlgorithm};
+use miner::stats::{ChainStats, DEFAULT_WINDOWS};
+use miner::target::Target;
+use config::{NetworkArgs, NetworkConfig};
+use network::{Client, NetworkConnector, NetworkStatus, TemplateFetcher};
+use outbox::Outbox;
+use p2p::{Node, PeerArgs};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 use rand::Rng;
-use rand::rngs::ThreadRng;
+use rand::SeedableRng;
+use rand::rngs::{StdRng, ThreadRng};
 
 const MY_NAME: &str = "miner1";
 
-#[derive(Default, Debug)]
-struct Blockchain {
-    /// The blockchain is represented as a simple tree with no
-    /// parent pointer.
-    blocks: TreeNode<Block>,
+/// File of the key signing the mined blocks, created by `miner keygen`.
+const DEFAULT_KEY_FILE: &str = "miner.key";
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    #[command(subcommand)]
+    action: Option<Commands>,
 }
 
-impl Blockchain {
-    /// Creates a new Blockchain from the provided genesis
-    /// block and vector of valid blocks.
-    pub fn new_from_genesis_and_vec(
-        genesis: Block,
-        blocks: Vec<Block>,
-    ) -> (Self, Vec<Block>) {
-        let mut tree = TreeNode::new(genesis);
-        let mut remaining_blocks = blocks;
-        let mut invalid_blocks = vec![];
-        let mut blockids = BlockHashSet::default();
-
-
-        let mut inserted_some = true;
-
-        while inserted_some {
-            inserted_some = false;
-            let mut still_remaining = Vec::new();
-
-            for block in remaining_blocks {
-                if blockids.contains(&block.nonce) {
-                    invalid_blocks.push(block);
-                    continue;
-                }
+#[derive(Subcommand)]
+enum Commands {
+    Mine {
+        /// Human readable alias shown next to the public key
+        #[arg(short)]
+        miner_name: Option<String>,
+        /// Key signing the mined blocks
+        #[arg(long, default_value = DEFAULT_KEY_FILE)]
+        key_file: PathBuf,
+        #[arg(long)]
+        max_iter: Option<u64>,
+        /// Number of threads searching for a nonce
+        #[arg(long, default_value_t = default_threads())]
+        threads: usize,
+        /// Rule choosing the block to mine on
+        #[arg(long, value_enum, default_value_t = ForkChoiceRule::Heaviest)]
+        fork_choice: ForkChoiceRule,
+        /// Stop after mining this number of blocks
+        #[arg(long)]
+        blocks: Option<u64>,
+        #[command(flatten)]
+        network: NetworkArgs,
+        #[command(flatten)]
+        peers: PeerArgs,
+    },
+    Print {
+        #[command(flatten)]
+        network: NetworkArgs,
+    },
+    /// Print the balance of a miner, known by its alias or its public key
+    Balance {
+        /// Alias, or public key in hex (a prefix is enough)
+        name: String,
+        #[command(flatten)]
+        network: NetworkArgs,
+    },
+    /// Print statistics of the chain of the server
+    Stats {
+        /// Windows of the dance move distribution, in blocks, 0 for the
+        /// whole best chain
+        #[arg(long = "window", value_delimiter = ',', default_values_t = DEFAULT_WINDOWS)]
+        windows: Vec<u64>,
+        /// Print JSON instead of tables
+        #[arg(long)]
+        json: bool,
+        #[command(flatten)]
+        network: NetworkArgs,
+    },
+    /// Check that a transaction is in the best chain of the server,
+    /// downloading only the block headers
+    Verify {
+        /// Transaction id, in hex
+        txid: String,
+        #[command(flatten)]
+        network: NetworkArgs,
+    },
+    /// Create the keypair identifying the miner
+    Keygen {
+        /// File the secret key is written to. It is never overwritten.
+        #[arg(long, default_value = DEFAULT_KEY_FILE)]
+        key_file: PathBuf,
+    },
+    /// Compare the hashrate of the proof of work algorithms
+    Bench {
+        /// Number of threads hashing
+        #[arg(long, default_value_t = default_threads())]
+        threads: usize,
+        /// Duration of the measure of each algorithm, in seconds
+        #[arg(long, default_value_t = 1.0)]
+        seconds: f64,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ForkChoiceRule {
+    /// Deepest block
+    Longest,
+    /// Most accumulated work
+    Heaviest,
+    /// Greedy Heaviest Observed SubTree
+    Ghost,
+}
 
-                if let Some(parent) = tree.look_for_parent(&block.parent_hash) {
-                    parent.insert(block.clone());
-                    blockids.insert(block.nonce);
-                    inserted_some = true;
-                } else {
-                    still_remaining.push(block);
+impl ForkChoiceRule {
+    fn build(self) -> Box<dyn ForkChoice> {
+        match self {
+            ForkChoiceRule::Longest => Box::new(LongestChain),
+            ForkChoiceRule::Heaviest => Box::new(HeaviestWork),
+            ForkChoiceRule::Ghost => Box::new(Ghost),
+        }
+    }
+}
+
+fn default_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Where the miner gets the blocks of the others from, and sends its own
+enum Network {
+    /// Servers relaying the blocks
+    Servers(Client, NetworkConfig),
+    /// Peers relaying the blocks to each other, without a server
+    Peers(PeerArgs),
+}
+
+fn mine(
+    miner_name: &Option<String>,
+    key_file: &Path,
+    max_iter: &Option<u64>,
+    threads: &usize,
+    fork_choice: &ForkChoiceRule,
+    max_blocks: &Option<u64>,
+    network: Network,
+) {
+    let key = match MinerKey::load(key_file) {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("Failed to read the key from {:?}: {}", key_file, e);
+            eprintln!("Create one with `miner keygen --key-file {}`", key_file.display());
+            return;
+        }
+    };
+    let public_key = key.public_key().to_vec();
+    let miner_name = miner_name.clone().unwrap_or_default();
+
+    // The difficulty is set by the chain, with the parameters of the
+    // server. Peers use the default ones.
+    let params = match &network {
+        Network::Servers(client, _) => loop {
+            match client.get_params() {
+                Ok(params) => break params,
+                Err(e) => {
+                    eprintln!("Failed to get the chain parameters: {:?}", e);
+                    thread::sleep(Duration::from_secs(1));
                 }
             }
+        },
+        Network::Peers(_) => ChainParams::default(),
+    };
+    let difficulty = &params.initial_difficulty;
+    let from_peers = matches!(network, Network::Peers(_));
+    let pow = params.pow.proof_of_work();
+    println!("Mining with {}", params.pow);
+
+    // Stop mining on Ctrl-C or SIGTERM, and let the network thread post
+    // the last blocks. A second signal exits at once.
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopping = stop.clone();
+    let handler = ctrlc::set_handler(move || {
+        if stopping.swap(true, Ordering::Relaxed) {
+            std::process::exit(130);
+        }
+        println!("Stopping, press Ctrl-C again to exit at once");
+    });
+    if let Err(e) = handler {
+        eprintln!("Failed to set the Ctrl-C handler: {}", e);
+    }
 
-            remaining_blocks = still_remaining;
+    // Create communication channels for the network
+    let (tx_net_send, rx_net) = mpsc::sync_channel(1);
+    let (tx_net, rx_net_ctrl) = mpsc::channel();
+
+    // Network thread for synchronization. The blocks of a previous run
+    // that the server didn't get yet are posted first.
+    let (templates, net_thread, net_status) = match network {
+        Network::Servers(client, config) => {
+            let outbox = match Outbox::open(&config.outbox) {
+                Ok(outbox) => outbox,
+                Err(e) => {
+                    eprintln!("Failed to read the outbox {:?}: {}", config.outbox, e);
+                    return;
+                }
+            };
+            if !outbox.is_empty() {
+                println!("{} blocks of {:?} to post", outbox.len(), config.outbox);
+            }
+            let mut net = NetworkConnector::new(client.clone(), &config, outbox, tx_net_send, rx_net_ctrl);
+            let status = net.status();
+            let templates = TemplateFetcher::start(client, config.poll_interval());
+            (Some(templates), Some(thread::spawn(move || net.sync())), Some(status))
         }
+        Network::Peers(args) => {
+            if let Err(e) = Node::start(&args, params, tx_net_send, rx_net_ctrl) {
+                eprintln!("Failed to start the peer-to-peer node: {}", e);
+                return;
+            }
+            (None, None, None)
+        }
+    };
 
-        remaining_blocks.extend(invalid_blocks);
+    let mut rng: ThreadRng = rand::rng();
 
-        (Blockchain { blocks: tree }, remaining_blocks)
-    }
+    // Wait for the blocks known by the server
+    let received = loop {
+        match rx_net.recv_timeout(Duration::from_millis(50)) {
+            Ok(received) => break received,
+            Err(RecvTimeoutError::Timeout) if !stop.load(Ordering::Relaxed) => {}
+            Err(RecvTimeoutError::Timeout) => return stop_network(tx_net, net_thread),
+            Err(RecvTimeoutError::Disconnected) => {
+                eprintln!("The network thread stopped");
+                return stop_network(tx_net, net_thread);
+            }
+        }
+    };
+
+    // Search or create a genesis block
+    let genesis = match received.iter().find(|b| b.is_genesis(*difficulty)) {
+        Some(genesis) => genesis.clone(),
+        None => {
+            let mut block = Block::new(vec![], "Genesis".to_string(), 0, random_dancemove(&mut rng));
+            block.timestamp = timestamp_now();
+            block.bits = params.initial_bits();
+            block.public_key = public_key.clone();
+            let target = block.target().unwrap();
+            block.solve_block_parallel(&mut rng, pow, &target, *threads, *max_iter, Some(stop.as_ref()));
+            if stop.load(Ordering::Relaxed) {
+                return stop_network(tx_net, net_thread);
+            }
+            block.sign(&key);
+            if tx_net.send(block.clone()).is_err() {
+                eprintln!("The network thread stopped");
+                return stop_network(tx_net, net_thread);
+            }
+            block
+        }
+    };
+
+    let mut chain = Blockchain::new(genesis)
+        .with_fork_choice(fork_choice.build())
+        .with_params(params);
+    update_chain(&mut chain, received, *difficulty, from_peers);
+
+    let mut mined = 0;
+    let mut last_status = NetworkStatus::default();
+    while max_blocks.is_none_or(|max_blocks| mined < max_blocks) && !stop.load(Ordering::Relaxed) {
+        // Take the blocks received while we were mining into account
+        if let Ok(blocks) = rx_net.try_recv() {
+            update_chain(&mut chain, blocks, *difficulty, from_peers);
+        }
 
-    fn print_tree(
-        &self,
-        f: &mut fmt::Formatter<'_>,
-        node: &TreeNode<Block>,
-        prefixes: &mut Vec<bool>,
-    ) -> fmt::Result {
-        // Print the current node
-        if !prefixes.is_empty() {
-            // Print connecting lines from parent
-            for &is_last in &prefixes[..prefixes.len() - 1] {
-                write!(f, "{}", if is_last { "    " } else { "│   " })?;
+        // Report when the server stops answering, and when it answers again.
+        // We keep mining on our chain meanwhile.
+        if let Some(status) = &net_status {
+            let status = status.lock().unwrap().clone();
+            if status != last_status && !(status.is_connected() && last_status.is_connected()) {
+                println!("{}", status);
             }
+            last_status = status;
+        }
 
-            // Print the appropriate connector
-            let is_last = *prefixes.last().unwrap();
-            write!(f, "{}", if is_last { "└── " } else { "├── " })?;
+        // Find the tip chosen by the fork choice rule
+        let leaf = chain.tip();
+
+        // Create and mine a new block. Blocks can be found faster than
+        // the clock ticks, and their timestamp must increase.
+        let timestamp = timestamp_now().max(chain.median_time_past(leaf) + 1);
+        let mut new_block = Block::new_child(
+            leaf.block(),
+            miner_name.clone(),
+            random_dancemove(&mut rng),
+            timestamp,
+            chain.next_bits(leaf),
+        );
+        new_block.public_key = public_key.clone();
+        // Mine the transactions waiting on the server, if it agrees on the
+        // tip. Until the template of the tip is fetched, the block has none.
+        let parent = *leaf.hash();
+        let template = templates.as_ref().and_then(|templates| templates.latest(&parent));
+        let has_template = template.is_some();
+        match template {
+            Some(template) => new_block.set_transactions(template.transactions),
+            None => templates.iter().for_each(TemplateFetcher::refresh),
         }
+        let mut solver_rng = StdRng::from_rng(&mut rng);
+        let cancel = AtomicBool::new(false);
+        let mut reason = ", restarting on the new tip";
+        let mut disconnected = false;
+
+        let (solution, stats) = thread::scope(|scope| {
+            let solver = scope.spawn(|| {
+                let target = new_block.target().unwrap();
+                new_block.solve_block_parallel(
+                    &mut solver_rng,
+                    pow,
+                    &target,
+                    *threads,
+                    *max_iter,
+                    Some(&cancel),
+                )
+            });
 
-        // Print the block info
-        let block = node.value();
-        writeln!(f, "{} (nonce: {})", block.miner, block.nonce)?;
+            // Keep following the network while mining, and give up on
+            // the block as soon as it's no longer mined on the tip, when
+            // the template of the tip arrives, or when stopping.
+            while !solver.is_finished() {
+                match rx_net.recv_timeout(Duration::from_millis(50)) {
+                    Ok(blocks) => {
+                        if update_chain(&mut chain, blocks, *difficulty, from_peers) {
+                            cancel.store(true, Ordering::Relaxed);
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => disconnected = true,
+                }
+                let template = templates.as_ref().filter(|_| !has_template).and_then(|templates| templates.latest(&parent));
+                if template.is_some_and(|template| !template.transactions.is_empty()) && !cancel.load(Ordering::Relaxed) {
+                    reason = ", restarting with the template of the server";
+                    cancel.store(true, Ordering::Relaxed);
+                }
+                if disconnected || stop.load(Ordering::Relaxed) {
+                    reason = ", stopping";
+                    cancel.store(true, Ordering::Relaxed);
+                }
+            }
+            solver.join().unwrap()
+        });
+
+        println!(
+            "{} hashes in {:.2}s ({:.0} H/s on {} threads){}",
+            stats.hashes,
+            stats.elapsed.as_secs_f64(),
+            stats.hashrate(),
+            threads,
+            if stats.cancelled { reason } else { "" }
+        );
+        if disconnected {
+            eprintln!("The network thread stopped");
+            break;
+        }
 
-        // Recursively print children
-        let child_count = node.children().len();
-        for (i, child) in node.children().iter().enumerate() {
-            prefixes.push(i == child_count - 1); // true if this is the last child
-            self.print_tree(f, child, prefixes)?;
-            prefixes.pop();
+        if solution.is_some() {
+            new_block.sign(&key);
+            // Mine the next block on top of ours without waiting for the server
+            update_chain(&mut chain, vec![new_block.clone()], *difficulty, from_peers);
+            if tx_net.send(new_block).is_err() {
+                eprintln!("The network thread stopped");
+                break;
+            }
+            mined += 1;
         }
+    }
 
-        Ok(())
+    stop_network(tx_net, net_thread);
+}
+
+/// Lets the network thread post the last blocks, and waits for it
+fn stop_network(tx_net: mpsc::Sender<Block>, net_thread: Option<thread::JoinHandle<()>>) {
+    drop(tx_net);
+    if let Some(net_thread) = net_thread {
+        if net_thread.join().is_err() {
+            eprintln!("The network thread failed");
+        }
     }
 }
 
-impl fmt::Display for Blockchain {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        self.print_tree(f, &self.blocks, &mut Vec::new())
+/// Adds the blocks we don't know yet to the chain. Returns true, after
+/// printing the chain, if the tip changed. `from_peers` is set when the
+/// blocks come from the peer-to-peer node, which sends the whole chain of
+/// another genesis block when it switches to it.
+fn update_chain(chain: &mut Blockchain, blocks: Vec<Block>, difficulty: u32, from_peers: bool) -> bool {
+    let mut tip_changed = false;
+
+    // When several miners create a genesis block at the same time, the
+    // server only accepts the first one, and we start over from it. Peers
+    // keep the chain with the most work, on a tie the node decides.
+    if let Some(genesis) = blocks.iter().find(|b| b.is_genesis(difficulty)) {
+        if &genesis.block_hash() != chain.genesis().hash() {
+            if from_peers && is_lighter(chain, genesis, &blocks) {
+                println!("Ignoring the lighter chain of the genesis block {}", genesis.block_hash());
+                return false;
+            }
+            chain.reset(genesis.clone());
+            tip_changed = true;
+        }
+    }
+
+    for block in blocks {
+        if let AddBlockOutcome::Inserted { tip_changed: true } = chain.add_block(block) {
+            tip_changed = true;
+        }
+    }
+
+    if tip_changed {
+        println!(
+            "Current blockchain state ({} orphans):\n{}",
+            chain.orphan_count(),
+            chain
+        );
     }
+    tip_changed
 }
 
-#[derive(Parser)]
-#[command(version, about)]
-struct Args {
-    #[command(subcommand)]
-    action: Option<Commands>,
+/// Whether the chain of `genesis` made of `blocks` has less work than
+/// `chain`
+fn is_lighter(chain: &Blockchain, genesis: &Block, blocks: &[Block]) -> bool {
+    let mut other = Blockchain::new(genesis.clone()).with_params(*chain.params());
+    for block in blocks {
+        other.add_block(block.clone());
+    }
+    other.tip().chainwork() < chain.tip().chainwork()
 }
 
-#[derive(Subcommand)]
-enum Commands {
-    Mine {
-        #[arg(short, default_value_t = DIFFICULTY)]
-        difficulty: u32,
-        #[arg(short, default_value_t = String::from(MY_NAME))]
-        miner_name: String,
-        #[arg(long)]
-        max_iter: Option<u64>,
-    },
-    Print {
-        #[arg(short, default_value_t = DIFFICULTY)]
-        difficulty: u32,
-    },
+/// Prints the accounts whose alias is `name`, or whose public key starts
+/// with `name`
+fn balance(client: &Client, name: &str) {
+    let balances = match client.get_balances() {
+        Ok(balances) => balances,
+        Err(e) => {
+            eprintln!("Failed to get the balances: {:?}", e);
+            return;
+        }
+    };
+    let name = name.to_lowercase();
+    let mut found = false;
+    for balance in balances {
+        if balance.miner.to_lowercase() == name || balance.public_key.starts_with(&name) {
+            println!(
+                "{} [{}]: {} coins, {} transactions sent",
+                balance.miner, balance.public_key, balance.balance, balance.nonce
+            );
+            found = true;
+        }
+    }
+    if !found {
+        println!("No account for {}", name);
+    }
 }
 
-fn mine(difficulty: &u32, miner_name: &String, max_iter: &Option<u64>) {
-    // Create communication channels for the network
-    let (tx_net_send, rx_net) = mpsc::sync_channel(1);
-    let (tx_net, rx_net_ctrl) = mpsc::channel();
+/// Prints the statistics of the chain of the server
+fn stats(client: &Client, windows: &[u64], json: bool) {
+    let stats = match client.get_stats(windows) {
+        Ok(stats) => stats,
+        Err(e) => {
+            eprintln!("Failed to get the statistics: {:?}", e);
+            return;
+        }
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&stats).unwrap());
+    } else {
+        print_stats(&stats);
+    }
+}
 
-    // Network thread for synchronization
-    thread::spawn(move || {
-        let mut net = NetworkConnector::new(tx_net_send, rx_net_ctrl);
-        net.sync().expect("Network failure");
-    });
+fn print_stats(stats: &ChainStats) {
+    println!(
+        "Height {}, {} blocks: {} stale, {} orphans",
+        stats.height, stats.blocks, stats.stale_blocks, stats.orphans
+    );
+    if let Some(interval) = stats.average_block_interval {
+        println!("Average block interval: {:.2} s", interval as f64 / 1000.0);
+    }
 
-    let mut rng: ThreadRng = rand::rng();
+    println!("\n{:<32} {:>8} {:>8}", "Miner", "Blocks", "Stale");
+    for miner in &stats.miners {
+        let name = format!("{} [{}]", miner.miner, &miner.public_key[..miner.public_key.len().min(8)]);
+        println!("{:<32} {:>8} {:>8}", name.trim_start(), miner.blocks, miner.stale_blocks);
+    }
 
-    loop {
-        let received = match rx_net.recv() {
-            Ok(blocks) => blocks,
-            Err(_) => {
-                eprintln!("Failed to receive from network.");
-                continue;
-            }
+    print!("\n{:<12} {:>8}", "Dance moves", "Blocks");
+    for dancemove in DanceMove::ALL {
+        print!(" {:>6}", format!("{:?}", dancemove));
+    }
+    println!();
+    for window in &stats.dance_moves {
+        let name = match window.window {
+            0 => "all".to_string(),
+            window => format!("last {}", window),
         };
+        print!("{:<12} {:>8}", name, window.blocks);
+        for dancemove in DanceMove::ALL {
+            print!(" {:>6}", window.counts.get(&dancemove).copied().unwrap_or_default());
+        }
+        println!();
+    }
 
-        // Search or create a genesis block
-        let genesis = received.iter()
-            .find(|b| b.is_genesis(*difficulty))
-            .cloned()
-            .unwrap_or_else(|| {
-                let mut block = Block::new(vec![], "Genesis".to_string(), 0, random_dancemove(&mut rng));
-                block.solve_block(&mut rng, *difficulty, *max_iter);
-                tx_net.send(block.clone()).expect("Failed to send genesis block");
-                block
-            });
+    println!("\n{:<12} {:>8}", "Fork depth", "Forks");
+    for (depth, forks) in &stats.fork_depths {
+        println!("{:<12} {:>8}", depth, forks);
+    }
+}
 
-        let (chain, _) = Blockchain::new_from_genesis_and_vec(genesis.clone(), received);
-
-        // Find the deepest leaf with the smallest nonce
-        let leaf = chain.blocks
-            .deepest_leafs()
-            .into_iter()
-            .min_by_key(|b| b.value().nonce)
-            .unwrap()
-            .value()
-            .clone();
-
-        // Create and mine a new block
-        let mut new_block = Block::new(
-            leaf.hash_block().to_vec(),
-            miner_name.to_string(),
-            0,
-            random_dancemove(&mut rng),
-        );
-        new_block.solve_block(&mut rng, *difficulty, *max_iter);
+/// Checks with a light client that the transaction `txid` is in the best
+/// chain: the headers are checked, then the Merkle proof of the transaction
+fn verify(client: &Client, txid: &str) {
+    let Some(txid) = parse_hex(txid) else {
+        eprintln!("A transaction id is made of 64 hexadecimal digits");
+        return;
+    };
+    let params = match client.get_params() {
+        Ok(params) => params,
+        Err(e) => {
+            eprintln!("Failed to get the chain parameters: {:?}", e);
+            return;
+        }
+    };
+    // Get the proof first, so that the headers include its block
+    let proof = match client.get_proof(&to_hex(&txid)) {
+        Ok(Some(proof)) => proof,
+        Ok(None) => {
+            println!("Transaction {} isn't in the best chain", to_hex(&txid));
+            return;
+        }
+        Err(e) => {
+            eprintln!("Failed to get the proof: {:?}", e);
+            return;
+        }
+    };
+    let headers = match client.get_headers() {
+        Ok(headers) => headers,
+        Err(e) => {
+            eprintln!("Failed to get the headers: {:?}", e);
+            return;
+        }
+    };
+
+    let mut client = LightClient::new(params);
+    for header in headers {
+        if let Err(reason) = client.add_header(header) {
+            eprintln!("Invalid header at height {}: {}", client.len(), reason);
+            return;
+        }
+    }
+    match client.verify(&txid, &proof) {
+        Ok(confirmations) => println!(
+            "Transaction {} is in block {} at height {}, {} confirmations",
+            to_hex(&txid),
+            proof.block_hash,
+            proof.height,
+            confirmations
+        ),
+        Err(reason) => eprintln!("Invalid proof: {}", reason),
+    }
+}
 
-        tx_net.send(new_block).expect("Failed to send block");
+/// Creates a keypair and writes it to `key_file`
+fn keygen(key_file: &Path) {
+    let key = MinerKey::generate(&mut rand::rng());
+    match key.save(key_file) {
+        Ok(()) => println!(
+            "Key written to {:?}, public key {}",
+            key_file,
+            to_hex(&key.public_key())
+        ),
+        Err(e) => eprintln!("Failed to write the key to {:?}: {}", key_file, e),
+    }
+}
 
-        println!("Current blockchain state:\n{}", chain);
+/// Hashes for `duration` with each proof of work algorithm, and prints
+/// the hashrate and the cost of checking a block.
+fn bench(threads: usize, duration: Duration) {
+    let mut rng = rand::rng();
+    let impossible = Target::from_leading_zeros(256);
+
+    for algorithm in PowAlgorithm::ALL {
+        let mut block = Block::new(vec![0; 32], MY_NAME.to_string(), 0, random_dancemove(&mut rng));
+        let cancel = AtomicBool::new(false);
+
+        let (_, stats) = thread::scope(|scope| {
+            let solver = scope.spawn(|| {
+                block.solve_block_parallel(
+                    &mut rand::rng(),
+                    algorithm.proof_of_work(),
+                    &impossible,
+                    threads,
+                    None,
+                    Some(&cancel),
+                )
+            });
+            thread::sleep(duration);
+            cancel.store(true, Ordering::Relaxed);
+            solver.join().unwrap()
+        });
+
+        // Each thread checks one nonce at a time
+        let check_time = stats.elapsed.as_secs_f64() * threads as f64 / stats.hashes.max(1) as f64;
+        println!(
+            "{:>14}: {:>12.0} H/s on {} threads, {:>8.2} µs per check",
+            algorithm.name(),
+            stats.hashrate(),
+            threads,
+            check_time * 1e6
+        );
     }
 }
 
@@ -197,26 +635,93 @@ fn random_dancemove(rng: &mut ThreadRng) -> DanceMove {
 
 
 
+/// Loads the network settings and creates the client of the servers
+fn connect(args: &NetworkArgs) -> Option<(Client, NetworkConfig)> {
+    let config = match args.load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid network settings: {}", e);
+            return None;
+        }
+    };
+    match Client::new(&config) {
+        Ok(client) => Some((client, config)),
+        Err(e) => {
+            eprintln!("Failed to create the HTTP client: {:?}", e);
+            None
+        }
+    }
+}
+
 fn main() {
 
     let args = Args::parse();
 
     match &args.action {
         Some(Commands::Mine {
-            difficulty,
             miner_name,
+            key_file,
             max_iter,
+            threads,
+            fork_choice,
+            blocks,
+            network,
+            peers,
         }) => {
-            mine(difficulty, miner_name, max_iter);
+            let network = if peers.is_enabled() {
+                Network::Peers(peers.clone())
+            } else {
+                let Some((client, config)) = connect(network) else {
+                    return;
+                };
+                Network::Servers(client, config)
+            };
+            mine(miner_name, key_file, max_iter, threads, fork_choice, blocks, network);
         }
 
-        Some(Commands::Print { difficulty: _ }) => {
+        Some(Commands::Balance { name, network }) => {
+            if let Some((client, _)) = connect(network) {
+                balance(&client, name);
+            }
+        }
+        Some(Commands::Stats { windows, json, network }) => {
+            if let Some((client, _)) = connect(network) {
+                stats(&client, windows, *json);
+            }
+        }
+        Some(Commands::Verify { txid, network }) => {
+            if let Some((client, _)) = connect(network) {
+                verify(&client, txid);
+            }
+        }
+
+        Some(Commands::Keygen { key_file }) => {
+            keygen(key_file);
+        }
+
+        Some(Commands::Bench { threads, seconds }) => {
+            bench(*threads, Duration::from_secs_f64(*seconds));
+        }
+
+        Some(Commands::Print { network }) => {
+            let Some((client, config)) = connect(network) else {
+                return;
+            };
+            // The blocks are checked with the parameters of the server
+            let params = match client.get_params() {
+                Ok(params) => params,
+                Err(e) => {
+                    eprintln!("Failed to get the chain parameters: {:?}", e);
+                    return;
+                }
+            };
             let (tx_net_send, rx_from_net) = mpsc::sync_channel(1);
             let (_tx_to_net, rx_for_net) = mpsc::channel();
 
+            // Nothing to post, the outbox is only in memory
             thread::spawn(move || {
-                let mut net = NetworkConnector::new(tx_net_send, rx_for_net);
-                net.sync().expect("Network synchronization failed");
+                let mut net = NetworkConnector::new(client, &config, Outbox::default(), tx_net_send, rx_for_net);
+                net.sync();
             });
 
             let received_blocks = match rx_from_net.recv() {
@@ -238,8 +743,10 @@ fn main() {
             };
 
             // Create the local blockchain from the received_blocks and the genesis block
-            let (blockchain, _remaining_blocks) =
-                Blockchain::new_from_genesis_and_vec(genesis, received_blocks);
+            let mut blockchain = Blockchain::new(genesis).with_params(params);
+            for block in received_blocks {
+                blockchain.add_block(block);
+            }
 
             println!("Current blockchain state:\n{}", blockchain);
         }
@@ -249,204 +756,8 @@ fn main() {
 
     }
 
-    #[cfg(test)]
-    mod tests {
-        use super::*;
-
-        fn create_test_block(parent_hash: &[u8], nonce_init: u64, miner: &str) -> Block {
-            Block::new(
-                parent_hash.to_vec(),
-                miner.to_string(),
-                nonce_init,
-                DanceMove::Y,
-            )
-        }
-
-        #[test]
-        fn test_empty_blocks() {
-            let genesis = create_test_block(&[], 0, "Genesis");
-            let (blockchain, _) =
-                Blockchain::new_from_genesis_and_vec(genesis, vec![]);
-
-            assert_eq!(blockchain.blocks.children().len(), 0);
-        }
-
-        #[test]
-        fn test_single_valid_block() {
-            let genesis = create_test_block(&[], 0, "Genesis");
-            let genesis_hash = genesis.hash_block().to_vec();
-
-            let block1 = create_test_block(&genesis_hash, 42, "miner1");
-            // let mut blockids = BlockHashSet::default();
-            let (blockchain, _) =
-                Blockchain::new_from_genesis_and_vec(genesis, vec![block1]);
-            // assert_eq!(blockids.len(), 1);
-
-            let root = &blockchain.blocks;
-            assert_eq!(root.children().len(), 1);
-            assert_eq!(root.children()[0].value().miner, "miner1");
-        }
-
-        #[test]
-        fn test_multiple_levels() {
-            let genesis = create_test_block(&[], 0, "Genesis");
-            let genesis_hash = genesis.hash_block().to_vec();
-
-            let block1 = create_test_block(&genesis_hash, 42, "miner1");
-            let block1_hash = block1.hash_block().to_vec();
-
-            let block2 = create_test_block(&genesis_hash, 43, "miner2");
-            let block3 = create_test_block(&block1_hash, 44, "miner3");
-
-            // let mut blockids = BlockHashSet::default();
-            let (blockchain, remaining) = Blockchain::new_from_genesis_and_vec(
-                genesis,
-                vec![block1, block2, block3],
-            );
-
-            //assert_eq!(blockids.len(), 3);
-
-            let root = &blockchain.blocks;
-            assert_eq!(root.children().len(), 2); // block1 and block2
-
-            // Find block1 in children
-            let block1_node = root
-                .children()
-                .iter()
-                .find(|n| n.value().miner == "miner1")
-                .unwrap();
-
-            assert_eq!(block1_node.children().len(), 1); // block3
-            assert_eq!(block1_node.children()[0].value().miner, "miner3");
-            assert!(remaining.is_empty());
-
-        }
-
-        #[test]
-        fn test_orphaned_blocks() {
-            let genesis = create_test_block(&[], 0, "Genesis");
-            let fake_hash = vec![0xFF; 32]; // Invalid parent hash
-
-            let valid_block = create_test_block(&genesis.hash_block().to_vec(), 42, "miner1");
-            let orphan_block = create_test_block(&fake_hash, 10, "miner2");
-
-            let (blockchain, _) = Blockchain::new_from_genesis_and_vec(
-                genesis,
-                vec![valid_block, orphan_block],
-            );
-
-            // Only valid_block should be added
-            assert_eq!(blockchain.blocks.children().len(), 1);
-            assert_eq!(blockchain.blocks.children()[0].value().miner, "miner1");
-        }
-
-        #[test]
-        fn test_duplicate_valid_blocks() {
-            let genesis = create_test_block(&[], 0, "Genesis");
-            let genesis_hash = genesis.hash_block().to_vec();
-
-            let block1 = create_test_block(&genesis_hash, 42, "miner1");
-            let block1_hash = block1.hash_block().to_vec();
-
-            let block2 = create_test_block(&genesis_hash, 43, "miner2");
-            let block3 = create_test_block(&block1_hash, 43, "miner3");
-
-            //let mut blockids = BlockHashSet::default();
-
-            let (blockchain, _) = Blockchain::new_from_genesis_and_vec(
-                genesis,
-                vec![block1, block2, block3],
-            );
-
-            //assert_eq!(blockids.len(), 2);
-
-            let root = &blockchain.blocks;
-            assert_eq!(root.children().len(), 2); // block1 and block2
-
-            // Find block1 in children
-            let block1_node = root
-                .children()
-                .iter()
-                .find(|n| n.value().miner == "miner1")
-                .unwrap();
-
-            assert_eq!(block1_node.children().len(), 0); // block3 not added
-        }
-
-        #[test]
-        fn test_complex_structure() {
-            let genesis = create_test_block(&[], 0, "Genesis");
-            let genesis_hash = genesis.hash_block().to_vec();
-
-            // Create blocks
-            let block1 = create_test_block(&genesis_hash, 42, "miner1");
-            let block1_hash = block1.hash_block().to_vec();
-
-            let block2 = create_test_block(&genesis_hash, 43, "miner2");
-            let block2_hash = block2.hash_block().to_vec();
-
-            let block3 = create_test_block(&block1_hash, 44, "miner3");
-            let block4 = create_test_block(&block2_hash, 45, "miner4");
-            let block5 = create_test_block(&block2_hash, 46, "miner5");
-
-            let (blockchain, _) = Blockchain::new_from_genesis_and_vec(
-                genesis,
-                vec![block1, block2, block3, block4, block5],
-            );
-
-            // Verify structure
-            let root = &blockchain.blocks;
-            assert_eq!(root.children().len(), 2);
-
-            let block1_node = root
-                .children()
-                .iter()
-                .find(|n| n.value().miner == "miner1")
-                .unwrap();
-            assert_eq!(block1_node.children().len(), 1);
-            assert_eq!(block1_node.children()[0].value().miner, "miner3");
-
-            let block2_node = root
-                .children()
-                .iter()
-                .find(|n| n.value().miner == "miner2")
-                .unwrap();
-            assert_eq!(block2_node.children().len(), 2);
-            assert!(block2_node
-                .children()
-                .iter()
-                .any(|n| n.value().miner == "miner4"));
-            assert!(block2_node
-                .children()
-                .iter()
-                .any(|n| n.value().miner == "miner5"));
-        }
-
-        #[test]
-        fn test_multiple_genesis() {
-            let genesis = create_test_block(&[], 0, "Genesis");
-            let genesis2 = create_test_block(&[], 42, "Genesis");
-
-            let genesis_hash = genesis.hash_block().to_vec();
-            let genesis2_hash = genesis2.hash_block().to_vec();
-
-            let block1 = create_test_block(&genesis_hash, 42, "miner1");
-            let block1_hash = block1.hash_block().to_vec();
-
-            let block2 = create_test_block(&genesis_hash, 43, "miner2");
-            let block3 = create_test_block(&block1_hash, 44, "miner3");
-
-            let block4 = create_test_block(&genesis2_hash, 42, "miner1");
-
-            let (_, remaining) = Blockchain::new_from_genesis_and_vec(
-                genesis,
-                vec![block1, block2, block3, block4],
-            );
-
-            assert_eq!(remaining.len(), 1);
-        }
-    }
-
-    mod block;
+    mod config;
+    mod events;
     mod network;
-    mod simpletree;
+    mod outbox;
+    mod p2p;
@@ -1,55 +1,716 @@
+use crate::config::NetworkConfig;
+use crate::events::Subscription;
+use crate::outbox::Outbox;
 use crate::Block;
-use std::sync::mpsc::Receiver;
-use std::sync::mpsc::SyncSender;
-use std::thread::sleep;
+use miner::ledger::Balance;
+use miner::lightclient::InclusionProof;
+use miner::mempool::BlockTemplate;
+use miner::block::BlockHash;
+use miner::params::ChainParams;
+use miner::stats::ChainStats;
+use miner::sync::{BlockPage, ChainEvent};
+use reqwest::blocking::{RequestBuilder, Response};
+use serde::de::DeserializeOwned;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::mpsc::{SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::{fmt, io};
+use std::thread::{self, sleep};
 use std::time::Duration;
 
-const URL: &str = "http://localhost:8080";
-const REQUEST_PAUSE_IN_SECONDS: u64 = 1;
+/// HTTP client of the servers. Requests go to the server that answered
+/// last, and to the next ones while it doesn't answer.
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::blocking::Client,
+    servers: Vec<String>,
+    connect_timeout: Duration,
+    /// Index in `servers` of the server that answered last, shared by the
+    /// clones of the client
+    current: Arc<AtomicUsize>,
+}
+
+impl Client {
+    /// Creates a client of the servers of `config`, with its timeouts
+    pub fn new(config: &NetworkConfig) -> reqwest::Result<Self> {
+        let http = reqwest::blocking::Client::builder()
+            .connect_timeout(config.connect_timeout())
+            .timeout(config.timeout())
+            .build()?;
+        let servers = config
+            .servers
+            .iter()
+            .map(|server| server.trim_end_matches('/').to_string())
+            .collect();
+        Ok(Client {
+            http,
+            servers,
+            connect_timeout: config.connect_timeout(),
+            current: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Sends the request built by `request` for the URL of a server, to
+    /// each server in turn until one answers. An internal server error
+    /// (5xx) doesn't count as an answer. Returns the last failure if no
+    /// server answers.
+    fn send(&self, request: impl Fn(&str) -> RequestBuilder) -> reqwest::Result<Response> {
+        self.send_indexed(request).map(|(_, response)| response)
+    }
+
+    /// Sends a request like [`Client::send`], and returns the index of the
+    /// server that answered along with its answer
+    fn send_indexed(&self, request: impl Fn(&str) -> RequestBuilder) -> reqwest::Result<(usize, Response)> {
+        let first = self.current.load(Ordering::Relaxed);
+        let mut failure = None;
+        for i in 0..self.servers.len() {
+            let index = (first + i) % self.servers.len();
+            match request(&self.servers[index]).send() {
+                Ok(response) if !response.status().is_server_error() => {
+                    if index != first {
+                        println!("Switched to the server {}", self.servers[index]);
+                        self.current.store(index, Ordering::Relaxed);
+                    }
+                    return Ok((index, response));
+                }
+                result => failure = Some(result.map(|response| (index, response))),
+            }
+        }
+        failure.expect("the config has at least one server")
+    }
+
+    fn get<T: DeserializeOwned>(&self, path: &str) -> reqwest::Result<T> {
+        self.send(|server| self.http.get(format!("{}{}", server, path)))?
+            .error_for_status()?
+            .json()
+    }
+
+    /// Posts a block in its binary encoding
+    pub fn post_block(&self, block: &Block) -> reqwest::Result<Response> {
+        let body = block.encode();
+        self.send(|server| {
+            self.http
+                .post(format!("{}/postblock", server))
+                .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+                .body(body.clone())
+        })
+    }
+
+    /// Blocks inserted in the chain of the server after the cursor
+    /// `since`, `None` if the server has no chain yet. The cursors of a
+    /// server mean nothing to the others, so the page comes with the index
+    /// of the server.
+    pub fn get_blocks_since(&self, since: u64) -> reqwest::Result<(usize, Option<BlockPage>)> {
+        let url = |server: &str| format!("{}/blocks?since={}", server, since);
+        let (server, response) = self.send_indexed(|server| self.http.get(url(server)))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok((server, None));
+        }
+        Ok((server, Some(response.error_for_status()?.json()?)))
+    }
+
+    /// Headers of the best chain of the server, genesis first
+    pub fn get_headers(&self) -> reqwest::Result<Vec<Block>> {
+        self.get("/headers")
+    }
+
+    /// Proof that the transaction `txid`, in hex, is in the best chain of
+    /// the server, `None` if it isn't
+    pub fn get_proof(&self, txid: &str) -> reqwest::Result<Option<InclusionProof>> {
+        let response = self.send(|server| self.http.get(format!("{}/proof/{}", server, txid)))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        response.error_for_status()?.json().map(Some)
+    }
+
+    pub fn get_balances(&self) -> reqwest::Result<Vec<Balance>> {
+        self.get("/balances")
+    }
+
+    /// Statistics of the chain of the server, with the dance move
+    /// distribution over each of `windows`
+    pub fn get_stats(&self, windows: &[u64]) -> reqwest::Result<ChainStats> {
+        let windows: Vec<String> = windows.iter().map(u64::to_string).collect();
+        self.get(&format!("/stats?windows={}", windows.join(",")))
+    }
+
+    pub fn get_template(&self) -> reqwest::Result<BlockTemplate> {
+        self.get("/template")
+    }
+
+    pub fn get_params(&self) -> reqwest::Result<ChainParams> {
+        self.get("/params")
+    }
+
+    /// Subscribes to the events pushed by the server that answered last,
+    /// and returns its index along with the subscription
+    pub fn subscribe(&self) -> io::Result<(usize, Subscription)> {
+        let index = self.current.load(Ordering::Relaxed);
+        let subscription = Subscription::connect(&self.servers[index], self.connect_timeout)?;
+        Ok((index, subscription))
+    }
+}
+
+/// What the network thread waits for.
+enum Input {
+    /// A block found by the miner, to post
+    Mined(Block),
+    /// An event pushed by the server, and the index of the server
+    Event(usize, ChainEvent),
+    /// The subscription to the events of the server started or ended
+    Subscribed(bool),
+    /// The miner stopped
+    Stop,
+}
+
+/// Subscribes to the events of the server and sends them to `inputs`,
+/// subscribing again after `retry` when the subscription ends
+fn subscribe(client: Client, inputs: Sender<Input>, retry: Duration) {
+    let mut warned = false;
+    loop {
+        let error = match client.subscribe() {
+            Ok((server, mut subscription)) => {
+                println!("Subscribed to the events of the server");
+                warned = false;
+                if inputs.send(Input::Subscribed(true)).is_err() {
+                    return;
+                }
+                let error = loop {
+                    match subscription.next_event() {
+                        Ok(event) => {
+                            if inputs.send(Input::Event(server, event)).is_err() {
+                                return;
+                            }
+                        }
+                        Err(e) => break e,
+                    }
+                };
+                if inputs.send(Input::Subscribed(false)).is_err() {
+                    return;
+                }
+                error
+            }
+            Err(e) => e,
+        };
+        // Only once, while the server can't push events we poll it
+        if !warned {
+            println!("No events from the server, polling it instead: {}", error);
+            warned = true;
+        }
+        sleep(retry);
+    }
+}
+
+/// Latest block template of the server, fetched in the background so that
+/// the miner never waits for the server.
+pub struct TemplateFetcher {
+    latest: Arc<Mutex<Option<BlockTemplate>>>,
+    refresh: Sender<()>,
+}
+
+impl TemplateFetcher {
+    /// Starts fetching the templates of the server, every `interval` and
+    /// whenever [`TemplateFetcher::refresh`] is called. It stops when the
+    /// fetcher is dropped.
+    pub fn start(client: Client, interval: Duration) -> Self {
+        let latest = Arc::new(Mutex::new(None));
+        let (refresh, requests) = mpsc::channel();
+        let fetched = latest.clone();
+        thread::spawn(move || {
+            let mut warned = false;
+            loop {
+                match client.get_template() {
+                    Ok(template) => {
+                        *fetched.lock().unwrap() = Some(template);
+                        warned = false;
+                    }
+                    // Only once, the miner mines without transactions
+                    // meanwhile
+                    Err(e) if !warned => {
+                        eprintln!("Failed to get the block template: {:?}", e);
+                        warned = true;
+                    }
+                    Err(_) => {}
+                }
+                match requests.recv_timeout(interval) {
+                    Ok(()) => while requests.try_recv().is_ok() {},
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+        });
+        TemplateFetcher { latest, refresh }
+    }
+
+    /// Asks for a new template, after the tip changed
+    pub fn refresh(&self) {
+        self.refresh.send(()).ok();
+    }
+
+    /// Last template fetched, if it is for the block `parent`
+    pub fn latest(&self, parent: &BlockHash) -> Option<BlockTemplate> {
+        let latest = self.latest.lock().unwrap();
+        latest.as_ref().filter(|template| &template.parent_hash == parent).cloned()
+    }
+}
+
+/// State of the connection to the servers, shared with the mining loop.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NetworkStatus {
+    /// Requests failed in a row, 0 while the servers answer
+    pub failures: u32,
+    /// Error of the last failed request
+    pub error: Option<String>,
+    /// Blocks waiting to be posted
+    pub outbox: usize,
+}
+
+impl NetworkStatus {
+    pub fn is_connected(&self) -> bool {
+        self.failures == 0
+    }
+}
+
+impl fmt::Display for NetworkStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_connected() {
+            write!(f, "Connected to the server")?;
+        } else {
+            write!(f, "Server unreachable, {} failed requests in a row", self.failures)?;
+            if let Some(error) = &self.error {
+                write!(f, " ({})", error)?;
+            }
+        }
+        if self.outbox > 0 {
+            write!(f, ", {} blocks waiting to be posted", self.outbox)?;
+        }
+        Ok(())
+    }
+}
 
 pub struct NetworkConnector {
+    client: Client,
+    // poll interval and backoff of the retries
+    config: NetworkConfig,
     // channel to send new blocks received from the server
     tx: SyncSender<Vec<Block>>,
-    // channel to receive a new block from the miner.
-    rx: Receiver<Block>,
+    // channel to receive the new blocks of the miner and the events of
+    // the server
+    inputs: Receiver<Input>,
+    events: Sender<Input>,
+    // blocks of the miner not posted yet
+    outbox: Outbox,
+    status: Arc<Mutex<NetworkStatus>>,
+    // genesis block of the chain of the server, and cursor of the next
+    // block to get from it. `server` is the index of the server the cursor
+    // comes from.
+    genesis: Option<BlockHash>,
+    cursor: u64,
+    server: Option<usize>,
+    // blocks received but not yet taken by the miner
+    pending: Vec<Block>,
 }
 
 impl NetworkConnector {
-    pub fn new(tx: SyncSender<Vec<Block>>, rx: Receiver<Block>) -> Self {
-        NetworkConnector { tx, rx }
+    pub fn new(
+        client: Client,
+        config: &NetworkConfig,
+        outbox: Outbox,
+        tx: SyncSender<Vec<Block>>,
+        rx: Receiver<Block>,
+    ) -> Self {
+        let (events, inputs) = mpsc::channel();
+        let mined = events.clone();
+        thread::spawn(move || {
+            for block in rx {
+                if mined.send(Input::Mined(block)).is_err() {
+                    return;
+                }
+            }
+            mined.send(Input::Stop).ok();
+        });
+        let status = NetworkStatus {
+            outbox: outbox.len(),
+            ..NetworkStatus::default()
+        };
+        NetworkConnector {
+            client,
+            config: config.clone(),
+            tx,
+            inputs,
+            events,
+            outbox,
+            status: Arc::new(Mutex::new(status)),
+            genesis: None,
+            cursor: 0,
+            server: None,
+            pending: Vec::new(),
+        }
+    }
+
+    /// State of the connection, updated as long as the connector runs
+    pub fn status(&self) -> Arc<Mutex<NetworkStatus>> {
+        self.status.clone()
     }
 
-    pub fn sync(&mut self) -> reqwest::Result<()> {
-        let mut fullurlpost: String = URL.to_owned();
-        fullurlpost.push_str("/postblock");
-        let client = reqwest::blocking::Client::new();
+    fn succeeded(&mut self) {
+        let mut status = self.status.lock().unwrap();
+        if !status.is_connected() {
+            println!("The server answers again");
+        }
+        status.failures = 0;
+        status.error = None;
+    }
 
+    fn failed(&mut self, error: impl fmt::Display) {
+        let mut status = self.status.lock().unwrap();
+        status.failures += 1;
+        status.error = Some(error.to_string());
+        println!(
+            "Request failed, retrying in {:?}: {}",
+            self.config.backoff(status.failures),
+            error
+        );
+    }
+
+    /// Posts the blocks of the outbox, oldest first, until a post fails.
+    /// Returns false if no server answered.
+    fn post_outbox(&mut self) -> bool {
+        let mut answered = true;
+        while let Some(block) = self.outbox.front().cloned() {
+            match self.client.post_block(&block) {
+                Ok(resp) if resp.status().is_server_error() => {
+                    self.failed(format!("the server answered {}", resp.status()));
+                    break;
+                }
+                Ok(resp) => {
+                    // Retrying a rejected block won't help
+                    if resp.status().is_client_error() {
+                        println!("An error occured: {:?}", resp.text().unwrap_or_default())
+                    }
+                    self.succeeded();
+                    if let Err(e) = self.outbox.pop() {
+                        eprintln!("Failed to save the outbox: {}", e);
+                    }
+                }
+                Err(e) => {
+                    self.failed(e);
+                    answered = false;
+                    break;
+                }
+            }
+        }
+        self.status.lock().unwrap().outbox = self.outbox.len();
+        answered
+    }
+
+    /// Gets the blocks the server inserted since the last call, all of
+    /// them the first time, when the server starts a new chain, or when
+    /// another server answers
+    fn fetch_new_blocks(&mut self) -> reqwest::Result<()> {
         loop {
-            // Check if the miner wants us to send any block
-            if let Ok(block) = self.rx.try_recv() {
-                let resp = client.post(&fullurlpost).json(&block).send()?;
+            let (server, page) = self.client.get_blocks_since(self.cursor)?;
+            // The cursor is from another server, which inserted its blocks
+            // in another order
+            if self.server.replace(server).is_some_and(|previous| previous != server) && self.cursor > 0 {
+                self.cursor = 0;
+                continue;
+            }
+            let Some(page) = page else {
+                // No genesis block yet
+                self.genesis = None;
+                self.cursor = 0;
+                return Ok(());
+            };
+            // The cursor is from another chain, start over
+            if self.genesis.is_some_and(|genesis| genesis != page.genesis) || page.next < self.cursor {
+                self.genesis = None;
+                self.cursor = 0;
+                self.pending.clear();
+                continue;
+            }
+            self.genesis = Some(page.genesis);
+            self.cursor = page.next;
+            self.pending.extend(page.blocks);
+            if self.cursor >= page.end {
+                return Ok(());
+            }
+        }
+    }
 
-                if resp.status().is_client_error() {
-                    println!("An error occured: {:?}", resp.text()?)
+    /// Takes an event of the server `server` into account. Returns false
+    /// if the blocks must be fetched to catch up with it.
+    fn apply(&mut self, server: usize, event: ChainEvent) -> bool {
+        if self.genesis.is_none() || self.server != Some(server) {
+            return false;
+        }
+        match event {
+            ChainEvent::Block { cursor, .. } if cursor <= self.cursor => true,
+            ChainEvent::Block { block, cursor } if cursor == self.cursor + 1 => {
+                self.cursor = cursor;
+                self.pending.push(block);
+                true
+            }
+            ChainEvent::Block { .. } => false,
+            ChainEvent::Tip(tip) => tip.cursor == self.cursor,
+        }
+    }
+
+    /// Sends the blocks of the server to the miner. If the miner did not
+    /// consume the previous ones, we keep them and retry later.
+    fn send_pending(&mut self) {
+        match self.tx.try_send(std::mem::take(&mut self.pending)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(blocks) | TrySendError::Disconnected(blocks)) => {
+                self.pending = blocks
+            }
+        }
+    }
+
+    /// Follows the server until the miner stops: posts the blocks of the
+    /// miner, and sends it the new blocks of the server as soon as it
+    /// pushes them, or polls it for them while it doesn't. Failed requests
+    /// are retried later and later, and the blocks wait in the outbox.
+    pub fn sync(&mut self) {
+        let (client, events, retry) = (self.client.clone(), self.events.clone(), self.config.poll_interval());
+        thread::spawn(move || subscribe(client, events, retry));
+
+        let mut subscribed = false;
+        let mut fetch = true;
+        loop {
+            // Don't count the same failure twice
+            let answered = self.post_outbox();
+            if fetch && answered {
+                // Recover the new blocks of the server and send them to the
+                // miner, even if there are none, so that it knows the
+                // server answered.
+                match self.fetch_new_blocks() {
+                    Ok(()) => {
+                        self.succeeded();
+                        self.send_pending();
+                    }
+                    Err(e) => self.failed(e),
                 }
+            } else if !self.pending.is_empty() {
+                self.send_pending();
             }
-            // Recover all the blocks known by the server and send
-            // them to the miner.
-            match get_blocks() {
-                // If the miner did not consume previous blocks; we drop them
-                // and retry in one second.
-                Ok(blocks) => self.tx.try_send(blocks).unwrap_or(()),
-                Err(e) => println!("Request failed {:?}", e),
+
+            let failures = self.status.lock().unwrap().failures;
+            fetch = match self.inputs.recv_timeout(self.config.backoff(failures)) {
+                Ok(Input::Mined(block)) => {
+                    if let Err(e) = self.outbox.push(block) {
+                        eprintln!("Failed to save the outbox: {}", e);
+                    }
+                    !subscribed
+                }
+                Ok(Input::Event(server, event)) => !self.apply(server, event),
+                // The server pushes its tip when subscribing
+                Ok(Input::Subscribed(now_subscribed)) => {
+                    subscribed = now_subscribed;
+                    !subscribed
+                }
+                // Time to poll, or to retry
+                Err(RecvTimeoutError::Timeout) => !subscribed || failures > 0,
+                Ok(Input::Stop) | Err(RecvTimeoutError::Disconnected) => break,
             };
+        }
 
-            sleep(Duration::from_secs(REQUEST_PAUSE_IN_SECONDS));
+        // Last chance for the blocks of the outbox, they are posted on the
+        // next start otherwise
+        self.post_outbox();
+        if let (false, Some(path)) = (self.outbox.is_empty(), self.outbox.path()) {
+            println!("{} blocks left in {:?}", self.outbox.len(), path);
         }
     }
 }
 
-pub fn get_blocks() -> reqwest::Result<Vec<Block>> {
-    let mut fullurl: String = URL.to_owned();
-    fullurl.push_str("/blocks");
-    let blocks: Vec<Block> = reqwest::blocking::get(fullurl)?.json()?;
-    Ok(blocks)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miner::block::DanceMove;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::time::Instant;
+
+    /// Serves `requests` requests with `answer`, which gets the path and
+    /// the body of a request and returns the status and the JSON body of
+    /// the response, then stops listening. Returns the URL of the server.
+    fn serve(requests: usize, answer: impl Fn(&str, &[u8]) -> (u16, String) + Send + 'static) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming().take(requests) {
+                let mut stream = BufReader::new(stream.unwrap());
+                let mut line = String::new();
+                stream.read_line(&mut line).unwrap();
+                let path = line.split_whitespace().nth(1).unwrap_or_default().to_string();
+                let mut length = 0;
+                while line != "\r\n" {
+                    line.clear();
+                    stream.read_line(&mut line).unwrap();
+                    if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                stream.read_exact(&mut body).unwrap();
+
+                let (status, body) = answer(&path, &body);
+                write!(
+                    stream.get_mut(),
+                    "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        url
+    }
+
+    /// Answers `GET /blocks?since=N` with the blocks of `chain` after the
+    /// first N
+    fn serve_chain(requests: usize, chain: Vec<Block>) -> String {
+        serve(requests, move |path, _| {
+            let since: usize = path.rsplit('=').next().unwrap().parse().unwrap();
+            let page = BlockPage {
+                genesis: chain[0].block_hash(),
+                blocks: chain[since.min(chain.len())..].to_vec(),
+                next: chain.len() as u64,
+                end: chain.len() as u64,
+            };
+            (200, serde_json::to_string(&page).unwrap())
+        })
+    }
+
+    fn connector(servers: Vec<String>) -> NetworkConnector {
+        let config = NetworkConfig {
+            servers,
+            poll_interval: 10,
+            connect_timeout: 500,
+            ..NetworkConfig::default()
+        };
+        let (tx, _) = mpsc::sync_channel(1);
+        let (_, rx) = mpsc::channel();
+        NetworkConnector::new(Client::new(&config).unwrap(), &config, Outbox::default(), tx, rx)
+    }
+
+    fn block(miner: &str) -> Block {
+        Block::new(vec![], miner.to_string(), 0, DanceMove::Y)
+    }
+
+    /// Answers `POST /postblock` with the statuses of `answers` in turn,
+    /// and records the blocks posted
+    fn serve_posts(answers: Vec<u16>) -> (String, Arc<Mutex<Vec<Block>>>) {
+        let posted = Arc::new(Mutex::new(Vec::new()));
+        let recorded = posted.clone();
+        let url = serve(answers.len(), move |_, body| {
+            let mut posted = recorded.lock().unwrap();
+            posted.push(Block::decode(body).unwrap());
+            (answers[posted.len() - 1], "{}".to_string())
+        });
+        (url, posted)
+    }
+
+    #[test]
+    fn test_outbox_retry_order() {
+        let blocks = vec![block("b1"), block("b2"), block("b3")];
+        // b1 fails, then b2 is rejected
+        let (url, posted) = serve_posts(vec![500, 200, 400, 200]);
+        let mut connector = connector(vec![url]);
+        for block in &blocks {
+            connector.outbox.push(block.clone()).unwrap();
+        }
+
+        // The server answered, with an error
+        assert!(connector.post_outbox());
+        assert_eq!(connector.outbox.len(), 3);
+        assert_eq!(connector.status.lock().unwrap().failures, 1);
+
+        // Retrying a rejected block won't help, the next one is posted
+        assert!(connector.post_outbox());
+        assert!(connector.outbox.is_empty());
+        let expected = [&blocks[0], &blocks[0], &blocks[1], &blocks[2]];
+        assert!(posted.lock().unwrap().iter().eq(expected));
+        assert_eq!(*connector.status.lock().unwrap(), NetworkStatus::default());
+    }
+
+    #[test]
+    fn test_outbox_reloaded_after_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("outbox");
+        let blocks = vec![block("b1"), block("b2")];
+
+        // No server listens, the blocks stay in the outbox
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let mut stopped = connector(vec![url]);
+        stopped.outbox = Outbox::open(&path).unwrap();
+        for block in &blocks {
+            stopped.outbox.push(block.clone()).unwrap();
+        }
+        assert!(!stopped.post_outbox());
+        drop(stopped);
+
+        // They are posted in order after a restart
+        let (url, posted) = serve_posts(vec![200, 200]);
+        let mut connector = connector(vec![url]);
+        connector.outbox = Outbox::open(&path).unwrap();
+        assert_eq!(connector.outbox.len(), 2);
+        assert!(connector.post_outbox());
+        assert_eq!(*posted.lock().unwrap(), blocks);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_template_fetcher() {
+        let (genesis, b1) = (block("Genesis"), block("b1"));
+        let template = BlockTemplate {
+            parent_hash: b1.block_hash(),
+            height: 2,
+            bits: 0,
+            transactions: vec![],
+        };
+        let answer = serde_json::to_string(&template).unwrap();
+        let config = NetworkConfig {
+            servers: vec![serve(1, move |_, _| (200, answer.clone()))],
+            ..NetworkConfig::default()
+        };
+        let templates = TemplateFetcher::start(Client::new(&config).unwrap(), Duration::from_secs(60));
+
+        // Only the template of the parent is returned
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while templates.latest(&b1.block_hash()).is_none() {
+            assert!(Instant::now() < deadline);
+            sleep(Duration::from_millis(10));
+        }
+        assert_eq!(templates.latest(&b1.block_hash()), Some(template));
+        assert_eq!(templates.latest(&genesis.block_hash()), None);
+    }
+
+    #[test]
+    fn test_failover_during_sync() {
+        let (genesis, b1, b2, x) = (block("Genesis"), block("b1"), block("b2"), block("x"));
+        // The second server inserted x before b1, so its cursors differ
+        let first = serve_chain(1, vec![genesis.clone(), b1.clone()]);
+        let second = serve_chain(2, vec![genesis.clone(), x.clone(), b1.clone(), b2.clone()]);
+        let mut connector = connector(vec![first, second]);
+
+        connector.fetch_new_blocks().unwrap();
+        assert_eq!(connector.pending, [genesis.clone(), b1.clone()]);
+        assert_eq!((connector.server, connector.cursor), (Some(0), 2));
+        // Events of another server don't move the cursor
+        let event = ChainEvent::Block { block: x.clone(), cursor: 3 };
+        assert!(!connector.apply(1, event));
+
+        // The first server is gone, all the blocks of the second one are
+        // fetched
+        connector.fetch_new_blocks().unwrap();
+        assert_eq!(connector.pending, [genesis.clone(), b1.clone(), genesis, x, b1, b2]);
+        assert_eq!((connector.server, connector.cursor), (Some(1), 4));
+    }
 }
@@ -0,0 +1,131 @@
+use crate::Block;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// Largest number of blocks waiting to be posted. The oldest ones are
+/// dropped beyond it, they are the least likely to end up on the best chain.
+const MAX_OUTBOX_SIZE: usize = 1000;
+
+/// Blocks mined but not posted yet, oldest first. They are saved in a file,
+/// one JSON block per line, so that they are posted after a restart.
+#[derive(Debug, Default)]
+pub struct Outbox {
+    /// Blocks are only kept in memory if not set
+    path: Option<PathBuf>,
+    blocks: VecDeque<Block>,
+}
+
+impl Outbox {
+    /// Opens the outbox saved at `path`, empty if there is none
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut blocks = VecDeque::new();
+        match File::open(path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    // The file is replaced at once, but skip what can't be
+                    // read rather than losing the rest
+                    match serde_json::from_str(&line) {
+                        Ok(block) => blocks.push_back(block),
+                        Err(e) => eprintln!("Dropping an invalid block of {:?}: {}", path, e),
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(Outbox {
+            path: Some(path.to_owned()),
+            blocks,
+        })
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Oldest block
+    pub fn front(&self) -> Option<&Block> {
+        self.blocks.front()
+    }
+
+    /// Adds a block, and saves the outbox. The block is kept in memory even
+    /// if it can't be saved.
+    pub fn push(&mut self, block: Block) -> io::Result<()> {
+        if self.blocks.len() == MAX_OUTBOX_SIZE {
+            self.blocks.pop_front();
+        }
+        self.blocks.push_back(block);
+        self.save()
+    }
+
+    /// Removes the oldest block, and saves the outbox
+    pub fn pop(&mut self) -> io::Result<()> {
+        self.blocks.pop_front();
+        self.save()
+    }
+
+    /// Replaces the file at once, so that a crash leaves either the old or
+    /// the new outbox. The file is removed when the outbox is empty.
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if self.blocks.is_empty() {
+            return match fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+        let mut content = Vec::new();
+        for block in &self.blocks {
+            serde_json::to_writer(&mut content, block)?;
+            content.push(b'\n');
+        }
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&content)?;
+        file.sync_data()?;
+        fs::rename(&tmp, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miner::block::DanceMove;
+
+    #[test]
+    fn test_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("outbox");
+        let blocks: Vec<Block> = (0..3)
+            .map(|nonce| Block::new(vec![], format!("miner{}", nonce), nonce, DanceMove::A))
+            .collect();
+
+        let mut outbox = Outbox::open(&path).unwrap();
+        assert!(outbox.is_empty());
+        for block in &blocks {
+            outbox.push(block.clone()).unwrap();
+        }
+        outbox.pop().unwrap();
+
+        let mut outbox = Outbox::open(&path).unwrap();
+        assert_eq!(outbox.len(), 2);
+        assert_eq!(outbox.front(), Some(&blocks[1]));
+        outbox.pop().unwrap();
+        outbox.pop().unwrap();
+        assert!(!path.exists());
+    }
+}
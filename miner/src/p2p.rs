@@ -0,0 +1,642 @@
+use crate::Block;
+use miner::block::BlockHash;
+use miner::chain::{check_structure, AddBlockOutcome, Blockchain};
+use miner::params::ChainParams;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Largest number of hashes of an inventory, and of blocks asked at once.
+const MAX_INV_SIZE: usize = 500;
+
+/// Largest message accepted from a peer, in bytes.
+const MAX_MESSAGE_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Time between two attempts to connect to a peer.
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// Time given to the peers to send their chain before mining.
+const INITIAL_SYNC_DELAY: Duration = Duration::from_secs(2);
+
+/// Time to send a message to a peer before dropping it.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest number of peers, both the ones we connect to and the ones that
+/// connect to us.
+const MAX_PEERS: usize = 32;
+
+/// Largest number of messages waiting to be sent to a peer. A peer that
+/// falls that far behind is dropped.
+const MAX_QUEUED_MESSAGES: usize = 1024;
+
+/// Largest number of chains kept aside, from other genesis blocks.
+const MAX_RIVAL_CHAINS: usize = 4;
+
+/// Command line flags of the peer-to-peer network. When set, the blocks go
+/// through the peers instead of a server.
+#[derive(Debug, Clone, clap::Args)]
+pub struct PeerArgs {
+    /// Address peers connect to, like 127.0.0.1:9000
+    #[arg(long)]
+    listen: Option<SocketAddr>,
+    /// Address of a peer to connect to. Several ones can be given
+    #[arg(long = "peer", value_delimiter = ',')]
+    peers: Vec<String>,
+}
+
+impl PeerArgs {
+    pub fn is_enabled(&self) -> bool {
+        self.listen.is_some() || !self.peers.is_empty()
+    }
+}
+
+/// Message between two peers, sent as a line of JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Message {
+    /// Asks for the hashes of the best chain of the receiver after the
+    /// first block of `locator` on it, from the genesis block if none is.
+    /// Sent when connecting, and for the parents of an orphan.
+    GetBlocks { locator: Vec<BlockHash> },
+    /// Blocks the sender has, parents before their children
+    Inv { hashes: Vec<BlockHash> },
+    /// Asks for the blocks of an inventory the receiver doesn't have
+    GetData { hashes: Vec<BlockHash> },
+    Block { block: Block },
+}
+
+fn send(stream: &mut TcpStream, message: &Message) -> io::Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    stream.write_all(&line)
+}
+
+fn read_message(reader: &mut impl BufRead) -> io::Result<Message> {
+    let mut line = Vec::new();
+    reader.take(MAX_MESSAGE_SIZE).read_until(b'\n', &mut line)?;
+    match line.last() {
+        Some(b'\n') => Ok(serde_json::from_slice(&line)?),
+        Some(_) if line.len() as u64 == MAX_MESSAGE_SIZE => {
+            Err(io::Error::new(ErrorKind::InvalidData, "message too large"))
+        }
+        _ => Err(ErrorKind::UnexpectedEof.into()),
+    }
+}
+
+/// Hashes of the best chain from the tip, every block of the last ten,
+/// then exponentially fewer, and the genesis block.
+fn locator(chain: &Blockchain) -> Vec<BlockHash> {
+    let mut locator = Vec::new();
+    let mut next = 0;
+    let mut step = 1;
+    for (i, node) in chain.best_chain().enumerate() {
+        if i == next || node.height() == 0 {
+            locator.push(*node.hash());
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            next += step;
+        }
+    }
+    locator
+}
+
+/// Checks what doesn't depend on the chain: the structure of the block,
+/// its signatures and its proof of work
+fn check_block(block: &Block, params: &ChainParams) -> Result<(), String> {
+    check_structure(block)
+        .and_then(|()| block.verify_signatures())
+        .map_err(|reason| reason.to_string())?;
+    // check_structure made sure the target is valid
+    let target = block.target().unwrap();
+    if !block.pow_check(&block.pow_hash(params.pow.proof_of_work()), &target) {
+        return Err("Invalid proof-of-work".to_string());
+    }
+    if block.parent_hash.is_empty()
+        && (!block.is_genesis(params.initial_difficulty) || block.bits != params.initial_bits())
+    {
+        return Err("Invalid genesis block".to_string());
+    }
+    Ok(())
+}
+
+/// Sends the messages queued for a peer, until the peer is dropped
+fn write_messages(mut stream: TcpStream, messages: Receiver<Message>) {
+    for message in messages {
+        if send(&mut stream, &message).is_err() {
+            // Its reading thread stops too
+            stream.shutdown(Shutdown::Both).ok();
+            return;
+        }
+    }
+}
+
+struct Peer {
+    address: SocketAddr,
+    /// Messages to the thread writing to the peer
+    messages: SyncSender<Message>,
+    stream: TcpStream,
+}
+
+struct State {
+    params: ChainParams,
+    /// Empty until a genesis block is mined or received
+    chain: Option<Blockchain>,
+    /// Chains from other genesis blocks, not heavier than `chain`. The
+    /// node switches to one of them as soon as it gets heavier.
+    rivals: Vec<Blockchain>,
+    peers: HashMap<usize, Peer>,
+    next_peer: usize,
+    /// Blocks inserted in the chain, for the miner
+    accepted: Sender<Vec<Block>>,
+}
+
+impl State {
+    fn locator(&self) -> Vec<BlockHash> {
+        self.chain.as_ref().map(locator).unwrap_or_default()
+    }
+
+    fn knows(&self, hash: &BlockHash) -> bool {
+        self.chain.iter().chain(&self.rivals).any(|chain| chain.contains(hash))
+    }
+
+    /// Answers the messages of a peer asking for blocks or announcing some
+    fn answer(&self, message: Message) -> Vec<Message> {
+        match message {
+            Message::GetBlocks { locator } => {
+                let Some(chain) = &self.chain else {
+                    return vec![];
+                };
+                let fork = locator
+                    .iter()
+                    .find(|hash| chain.is_on_best_chain(hash))
+                    .and_then(|hash| chain.get(hash));
+                let mut hashes: Vec<BlockHash> = chain
+                    .best_chain()
+                    .take_while(|node| fork.is_none_or(|fork| node.height() > fork.height()))
+                    .map(|node| *node.hash())
+                    .collect();
+                hashes.reverse();
+                hashes.truncate(MAX_INV_SIZE);
+                if hashes.is_empty() {
+                    return vec![];
+                }
+                vec![Message::Inv { hashes }]
+            }
+            Message::Inv { hashes } => {
+                let missing: Vec<BlockHash> = hashes.iter().filter(|hash| !self.knows(hash)).copied().collect();
+                let mut messages = vec![];
+                if !missing.is_empty() {
+                    messages.push(Message::GetData { hashes: missing });
+                }
+                // A full inventory, ask for the next blocks once they are in
+                if let (MAX_INV_SIZE, Some(last)) = (hashes.len(), hashes.last()) {
+                    let mut locator = vec![*last];
+                    locator.extend(self.locator());
+                    messages.push(Message::GetBlocks { locator });
+                }
+                messages
+            }
+            Message::GetData { hashes } => {
+                let Some(chain) = &self.chain else {
+                    return vec![];
+                };
+                hashes
+                    .iter()
+                    .take(MAX_INV_SIZE)
+                    .filter_map(|hash| chain.get(hash))
+                    .map(|node| Message::Block { block: node.block().clone() })
+                    .collect()
+            }
+            Message::Block { .. } => vec![],
+        }
+    }
+
+    /// Queues a message for a peer, dropping the peer if it is gone or
+    /// too slow
+    fn send_to(&mut self, id: usize, message: &Message) {
+        let Some(peer) = self.peers.get(&id) else {
+            return;
+        };
+        if let Err(e) = peer.messages.try_send(message.clone()) {
+            let reason = match e {
+                TrySendError::Full(_) => "too many messages waiting",
+                TrySendError::Disconnected(_) => "disconnected",
+            };
+            println!("Dropping the peer {}: {}", peer.address, reason);
+            // Its reading thread stops too
+            peer.stream.shutdown(Shutdown::Both).ok();
+            self.peers.remove(&id);
+        }
+    }
+
+    /// Sends a message to every peer but `except`
+    fn broadcast(&mut self, message: &Message, except: Option<usize>) {
+        let ids: Vec<usize> = self.peers.keys().copied().filter(|&id| Some(id) != except).collect();
+        for id in ids {
+            self.send_to(id, message);
+        }
+    }
+
+    /// Adds a checked block to the chain, or to the rival chain of its
+    /// parent. Returns the blocks inserted in the chain, all the blocks of
+    /// a rival chain when it gets heavier and replaces the chain, and
+    /// whether the block is an orphan.
+    fn add_block(&mut self, block: Block, sender: &str) -> (Vec<Block>, bool) {
+        let params = self.params;
+        let Some(chain) = &mut self.chain else {
+            if !block.parent_hash.is_empty() {
+                return (vec![], true);
+            }
+            self.chain = Some(Blockchain::new(block.clone()).with_params(params));
+            return (vec![block], false);
+        };
+
+        // When several genesis blocks were mined, the chain with the most
+        // work wins, the others are kept aside in case they catch up
+        if block.parent_hash.is_empty() {
+            let hash = block.block_hash();
+            if *chain.genesis().hash() != hash && !self.rivals.iter().any(|rival| *rival.genesis().hash() == hash) {
+                println!("Keeping aside the genesis block {} of {}", hash, sender);
+                if self.rivals.len() == MAX_RIVAL_CHAINS {
+                    let lightest = (0..self.rivals.len()).min_by_key(|&i| self.rivals[i].tip().chainwork());
+                    self.rivals.remove(lightest.unwrap_or_default());
+                }
+                self.rivals.push(Blockchain::new(block).with_params(params));
+            }
+            return (vec![], false);
+        }
+        let rival = block
+            .parent()
+            .and_then(|parent| self.rivals.iter().position(|rival| rival.contains(&parent)));
+        let Some(index) = rival else {
+            let cursor = chain.cursor();
+            return match chain.add_block(block) {
+                AddBlockOutcome::Inserted { .. } => {
+                    (chain.inserted_since(cursor).map(|node| node.block().clone()).collect(), false)
+                }
+                AddBlockOutcome::Orphaned => (vec![], true),
+                AddBlockOutcome::Duplicate => (vec![], false),
+                AddBlockOutcome::Invalid(reason) => {
+                    println!("Rejected a block from {}: {}", sender, reason);
+                    (vec![], false)
+                }
+            };
+        };
+
+        let rival = &mut self.rivals[index];
+        if let AddBlockOutcome::Invalid(reason) = rival.add_block(block) {
+            println!("Rejected a block from {}: {}", sender, reason);
+        }
+        if rival.tip().chainwork() <= chain.tip().chainwork() {
+            return (vec![], false);
+        }
+        println!("Switching to the heavier chain of the genesis block {}", rival.genesis().hash());
+        let rival = self.rivals.remove(index);
+        let blocks = rival.inserted_since(0).map(|node| node.block().clone()).collect();
+        if let Some(previous) = self.chain.replace(rival) {
+            self.rivals.push(previous);
+        }
+        (blocks, false)
+    }
+}
+
+/// Node of the peer-to-peer network. It keeps its own chain, checking
+/// every block, announces the blocks it inserts to its peers, and gets the
+/// blocks they announce.
+#[derive(Clone)]
+pub struct Node {
+    state: Arc<Mutex<State>>,
+}
+
+impl Node {
+    /// Starts the node: listens for peers, connects to the peers of `args`,
+    /// posts the blocks received on `rx` and sends the blocks inserted in
+    /// its chain on `tx`, all of them first, after the initial sync.
+    pub fn start(
+        args: &PeerArgs,
+        params: ChainParams,
+        tx: SyncSender<Vec<Block>>,
+        rx: Receiver<Block>,
+    ) -> io::Result<Self> {
+        let (node, blocks) = Node::new(params);
+        if let Some(address) = args.listen {
+            node.listen(TcpListener::bind(address)?);
+            println!("Listening for peers on {}", address);
+        }
+
+        for address in args.peers.clone() {
+            let node = node.clone();
+            thread::spawn(move || node.connect(&address));
+        }
+
+        let mined = node.clone();
+        thread::spawn(move || {
+            for block in rx {
+                mined.receive(block, None);
+            }
+        });
+
+        thread::spawn(move || {
+            thread::sleep(INITIAL_SYNC_DELAY);
+            let mut pending = Vec::new();
+            loop {
+                pending.extend(blocks.try_iter().flatten());
+                if tx.send(std::mem::take(&mut pending)).is_err() {
+                    return;
+                }
+                match blocks.recv() {
+                    Ok(inserted) => pending.extend(inserted),
+                    Err(_) => return,
+                }
+            }
+        });
+
+        Ok(node)
+    }
+
+    /// Creates a node without peers nor chain, and the channel of the
+    /// blocks it inserts in its chain. A chain switch comes at once.
+    fn new(params: ChainParams) -> (Self, Receiver<Vec<Block>>) {
+        let (accepted, blocks) = mpsc::channel();
+        let state = State {
+            params,
+            chain: None,
+            rivals: Vec::new(),
+            peers: HashMap::new(),
+            next_peer: 0,
+            accepted,
+        };
+        let node = Node {
+            state: Arc::new(Mutex::new(state)),
+        };
+        (node, blocks)
+    }
+
+    /// Accepts the peers connecting to `listener`
+    fn listen(&self, listener: TcpListener) {
+        let node = self.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let node = node.clone();
+                thread::spawn(move || node.run_peer(stream));
+            }
+        });
+    }
+
+    /// Keeps connecting to the peer at `address`
+    fn connect(&self, address: &str) {
+        let mut warned = false;
+        loop {
+            match TcpStream::connect(address) {
+                Ok(stream) => {
+                    println!("Connected to the peer {}", address);
+                    warned = false;
+                    if let Err(e) = self.run_peer(stream) {
+                        println!("Disconnected from the peer {}: {}", address, e);
+                    }
+                }
+                Err(e) if !warned => {
+                    println!("Failed to connect to the peer {}: {}", address, e);
+                    warned = true;
+                }
+                Err(_) => {}
+            }
+            thread::sleep(RECONNECT_DELAY);
+        }
+    }
+
+    /// Syncs with a connected peer, then handles its messages until it
+    /// disconnects
+    fn run_peer(&self, stream: TcpStream) -> io::Result<()> {
+        let address = stream.peer_addr()?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let writer = stream.try_clone()?;
+        let (messages, queue) = mpsc::sync_channel(MAX_QUEUED_MESSAGES);
+        let id = {
+            let mut state = self.state.lock().unwrap();
+            if state.peers.len() >= MAX_PEERS {
+                stream.shutdown(Shutdown::Both).ok();
+                return Err(io::Error::other("too many peers"));
+            }
+            let id = state.next_peer;
+            state.next_peer += 1;
+            state.peers.insert(id, Peer { address, messages, stream });
+            let locator = state.locator();
+            state.send_to(id, &Message::GetBlocks { locator });
+            id
+        };
+        thread::spawn(move || write_messages(writer, queue));
+
+        let error = loop {
+            match read_message(&mut reader) {
+                Ok(Message::Block { block }) => self.receive(block, Some(id)),
+                Ok(message) => {
+                    let mut state = self.state.lock().unwrap();
+                    for answer in state.answer(message) {
+                        state.send_to(id, &answer);
+                    }
+                }
+                Err(e) => break e,
+            }
+        };
+        self.state.lock().unwrap().peers.remove(&id);
+        Err(error)
+    }
+
+    /// Adds a block, mined if `from` is `None`, or sent by the peer `from`,
+    /// to the chain. The blocks inserted are announced to the other peers.
+    /// Nothing is written to the peers here, their messages are queued.
+    fn receive(&self, block: Block, from: Option<usize>) {
+        let mut state = self.state.lock().unwrap();
+        let sender = match from.and_then(|id| state.peers.get(&id)) {
+            Some(peer) => peer.address.to_string(),
+            None => "the miner".to_string(),
+        };
+        if let Err(reason) = check_block(&block, &state.params) {
+            println!("Rejected a block from {}: {}", sender, reason);
+            return;
+        }
+
+        let (inserted, orphaned) = state.add_block(block, &sender);
+
+        // Ask the peer for the parents of an orphan
+        if let (true, Some(id)) = (orphaned, from) {
+            let locator = state.locator();
+            state.send_to(id, &Message::GetBlocks { locator });
+        }
+        if !inserted.is_empty() {
+            // Parents first, the peers ask for the next ones of a full
+            // inventory
+            let mut hashes: Vec<BlockHash> = inserted.iter().map(Block::block_hash).collect();
+            hashes.truncate(MAX_INV_SIZE);
+            state.broadcast(&Message::Inv { hashes }, from);
+            state.accepted.send(inserted).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miner::block::DanceMove;
+    use miner::identity::MinerKey;
+    use std::time::Instant;
+
+    fn params() -> ChainParams {
+        ChainParams {
+            initial_difficulty: 4,
+            ..ChainParams::default()
+        }
+    }
+
+    /// Signs the block and finds its proof of work
+    fn mine(mut block: Block) -> Block {
+        let key = MinerKey::from_seed([1; 32]);
+        block.bits = params().initial_bits();
+        block.public_key = key.public_key().to_vec();
+        let target = block.target().unwrap();
+        let pow = params().pow.proof_of_work();
+        while !block.pow_check(&block.pow_hash(pow), &target) {
+            block.nonce += 1;
+        }
+        block.sign(&key);
+        block
+    }
+
+    fn new_genesis(dance_move: DanceMove) -> Block {
+        mine(Block::new(vec![], "Genesis".to_string(), 0, dance_move))
+    }
+
+    fn child(parent: &Block) -> Block {
+        let bits = params().initial_bits();
+        mine(Block::new_child(parent, "miner1".to_string(), DanceMove::A, parent.timestamp + 1, bits))
+    }
+
+    /// Waits for the blocks a node inserts until there are `count` of them
+    fn wait_for(blocks: &Receiver<Vec<Block>>, count: usize) -> Vec<Block> {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut received = Vec::new();
+        while received.len() < count {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            received.extend(blocks.recv_timeout(timeout).unwrap());
+        }
+        received
+    }
+
+    #[test]
+    fn test_receive() {
+        let (node, blocks) = Node::new(params());
+        let genesis = new_genesis(DanceMove::Y);
+        let b1 = child(&genesis);
+        let b2 = child(&b1);
+
+        // Nothing to attach an orphan to before the genesis block
+        node.receive(b1.clone(), None);
+        assert!(blocks.try_recv().is_err());
+        node.receive(genesis.clone(), None);
+        assert_eq!(blocks.try_recv().unwrap(), std::slice::from_ref(&genesis));
+
+        // The orphan is inserted with its parent
+        node.receive(b2.clone(), None);
+        assert!(blocks.try_recv().is_err());
+        node.receive(b1.clone(), None);
+        assert_eq!(blocks.try_recv().unwrap(), [b1.clone(), b2.clone()]);
+        node.receive(b1.clone(), None);
+        assert!(blocks.try_recv().is_err());
+
+        // The signature no longer matches
+        let mut invalid = child(&b2);
+        invalid.miner = "miner2".to_string();
+        node.receive(invalid, None);
+        assert!(blocks.try_recv().is_err());
+
+        // Another genesis block only wins once its chain has more work
+        let other = new_genesis(DanceMove::A);
+        let c1 = child(&other);
+        let c2 = child(&c1);
+        let c3 = child(&c2);
+        for block in [&other, &c1, &c2] {
+            node.receive(block.clone(), None);
+        }
+        assert!(blocks.try_recv().is_err());
+        assert_eq!(node.state.lock().unwrap().chain.as_ref().unwrap().genesis().block(), &genesis);
+        node.receive(c3.clone(), None);
+        assert_eq!(blocks.try_recv().unwrap(), [other.clone(), c1, c2, c3]);
+        let state = node.state.lock().unwrap();
+        assert_eq!(state.chain.as_ref().unwrap().genesis().block(), &other);
+        // The previous chain is kept in case it catches up
+        assert_eq!(state.rivals.len(), 1);
+        assert!(state.knows(&b2.block_hash()));
+    }
+
+    #[test]
+    fn test_tcp_exchange() {
+        let genesis = new_genesis(DanceMove::Y);
+        let b1 = child(&genesis);
+        let b2 = child(&b1);
+
+        let (first, first_blocks) = Node::new(params());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        first.listen(listener);
+        first.receive(genesis.clone(), None);
+        first.receive(b1.clone(), None);
+        assert_eq!(wait_for(&first_blocks, 2), [genesis.clone(), b1.clone()]);
+
+        // The second node gets the chain of the first one when it connects
+        let (second, second_blocks) = Node::new(params());
+        let connecting = second.clone();
+        thread::spawn(move || connecting.connect(&address));
+        assert_eq!(wait_for(&second_blocks, 2), [genesis, b1]);
+
+        // Then the first node gets the blocks mined by the second one
+        second.receive(b2.clone(), None);
+        assert_eq!(wait_for(&first_blocks, 1), [b2]);
+    }
+
+    #[test]
+    fn test_answer() {
+        let genesis = Block::new(vec![], "Genesis".to_string(), 0, DanceMove::Y);
+        let mut chain = Blockchain::new(genesis.clone());
+        let mut blocks = vec![genesis];
+        for timestamp in 1..=20 {
+            let parent = blocks.last().unwrap();
+            let bits = chain.next_bits(chain.get(&parent.block_hash()).unwrap());
+            let block = Block::new_child(parent, String::new(), DanceMove::A, timestamp, bits);
+            assert!(matches!(chain.add_block(block.clone()), AddBlockOutcome::Inserted { .. }));
+            blocks.push(block);
+        }
+        let hashes: Vec<BlockHash> = blocks.iter().map(Block::block_hash).collect();
+        let locator = locator(&chain);
+        assert_eq!(locator[..10], hashes.iter().rev().take(10).copied().collect::<Vec<_>>()[..]);
+        assert_eq!(locator[10..], [hashes[9], hashes[5], hashes[0]]);
+
+        let (accepted, _) = mpsc::channel();
+        let state = State {
+            params: ChainParams::default(),
+            chain: Some(chain),
+            rivals: Vec::new(),
+            peers: HashMap::new(),
+            next_peer: 0,
+            accepted,
+        };
+        // From the first block of the locator on the best chain
+        let answer = state.answer(Message::GetBlocks { locator: vec![BlockHash([1; 32]), hashes[17]] });
+        assert_eq!(answer, [Message::Inv { hashes: hashes[18..].to_vec() }]);
+        let answer = state.answer(Message::GetBlocks { locator: vec![] });
+        assert_eq!(answer, [Message::Inv { hashes: hashes.clone() }]);
+        assert!(state.answer(Message::GetBlocks { locator: vec![hashes[20]] }).is_empty());
+
+        let answer = state.answer(Message::Inv { hashes: vec![hashes[3], BlockHash([1; 32])] });
+        assert_eq!(answer, [Message::GetData { hashes: vec![BlockHash([1; 32])] }]);
+        let answer = state.answer(Message::GetData { hashes: vec![BlockHash([1; 32]), hashes[3]] });
+        assert_eq!(answer, [Message::Block { block: blocks[3].clone() }]);
+    }
+}
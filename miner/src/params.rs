@@ -0,0 +1,209 @@
+use crate::block::{Blake3, DoubleSha256, ProofOfWork, Scrypt, SingleSha256, DIFFICULTY};
+use crate::target::Target;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Largest factor by which a single retarget can change the target.
+pub const MAX_RETARGET_FACTOR: u128 = 4;
+
+/// Lowest difficulty, in leading zero bits, retargeting can go down to.
+pub const MIN_DIFFICULTY: u32 = 1;
+
+/// Coins credited to the miner of each block, unless configured otherwise.
+pub const DEFAULT_BLOCK_REWARD: u64 = 50;
+
+/// The proof of work algorithms a chain can be configured with.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PowAlgorithm {
+    /// See [`SingleSha256`]
+    #[default]
+    Sha256,
+    /// See [`DoubleSha256`]
+    DoubleSha256,
+    /// See [`Blake3`]
+    Blake3,
+    /// See [`Scrypt`]
+    Scrypt,
+}
+
+impl PowAlgorithm {
+    /// All the algorithms, from the cheapest to the most expensive to check
+    pub const ALL: [PowAlgorithm; 4] = [
+        PowAlgorithm::Blake3,
+        PowAlgorithm::Sha256,
+        PowAlgorithm::DoubleSha256,
+        PowAlgorithm::Scrypt,
+    ];
+
+    /// Get the implementation of the algorithm
+    pub fn proof_of_work(self) -> &'static dyn ProofOfWork {
+        match self {
+            PowAlgorithm::Sha256 => &SingleSha256,
+            PowAlgorithm::DoubleSha256 => &DoubleSha256,
+            PowAlgorithm::Blake3 => &Blake3,
+            PowAlgorithm::Scrypt => &Scrypt,
+        }
+    }
+
+    /// Name of the algorithm, as accepted by [`PowAlgorithm::from_str`]
+    pub fn name(self) -> &'static str {
+        match self {
+            PowAlgorithm::Sha256 => "sha256",
+            PowAlgorithm::DoubleSha256 => "double-sha256",
+            PowAlgorithm::Blake3 => "blake3",
+            PowAlgorithm::Scrypt => "scrypt",
+        }
+    }
+}
+
+impl fmt::Display for PowAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Error returned when parsing an unknown [`PowAlgorithm`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsePowAlgorithmError;
+
+impl fmt::Display for ParsePowAlgorithmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the proof of work algorithm must be one of")?;
+        for algorithm in PowAlgorithm::ALL {
+            write!(f, " {}", algorithm)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParsePowAlgorithmError {}
+
+impl FromStr for PowAlgorithm {
+    type Err = ParsePowAlgorithmError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PowAlgorithm::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.name() == s)
+            .ok_or(ParsePowAlgorithmError)
+    }
+}
+
+/// Consensus parameters every node of a chain must agree on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ChainParams {
+    /// Difficulty, in leading zero bits, of the genesis block and of the
+    /// first retarget window.
+    pub initial_difficulty: u32,
+    /// Time between two blocks the retargeting aims for, in milliseconds.
+    pub target_block_time: u64,
+    /// Number of blocks between two difficulty adjustments, 0 to never
+    /// adjust it.
+    pub retarget_window: u64,
+    /// Hash function of the proof of work.
+    #[serde(default)]
+    pub pow: PowAlgorithm,
+    /// Coins credited to the miner of each block, see [`crate::ledger`].
+    pub block_reward: u64,
+}
+
+impl Default for ChainParams {
+    fn default() -> Self {
+        ChainParams {
+            initial_difficulty: DIFFICULTY,
+            target_block_time: 5_000,
+            retarget_window: 10,
+            pow: PowAlgorithm::default(),
+            block_reward: DEFAULT_BLOCK_REWARD,
+        }
+    }
+}
+
+impl ChainParams {
+    /// Compact target of the genesis block and of the first retarget window
+    pub fn initial_bits(&self) -> u32 {
+        Target::from_leading_zeros(self.initial_difficulty).to_compact()
+    }
+
+    /// Returns true if a block at `height` starts a new retarget window
+    pub fn is_retarget_height(&self, height: u64) -> bool {
+        height > 0 && height.is_multiple_of(self.retarget_window)
+    }
+
+    /// Adjusts the compact target `bits` given the time it took to mine
+    /// `blocks` blocks: the target is multiplied by the actual time over
+    /// the expected one, by [`MAX_RETARGET_FACTOR`] at most, and never gets
+    /// easier than [`MIN_DIFFICULTY`].
+    pub fn retarget(&self, bits: u32, blocks: u64, elapsed: u64) -> u32 {
+        let limit = Target::from_leading_zeros(MIN_DIFFICULTY);
+        let expected = (u128::from(self.target_block_time) * u128::from(blocks)).max(1);
+        let actual = u128::from(elapsed).clamp(
+            (expected / MAX_RETARGET_FACTOR).max(1),
+            expected * MAX_RETARGET_FACTOR,
+        );
+
+        let target = Target::from_compact(bits).unwrap_or(limit);
+        target.scale(actual, expected).min(limit).to_compact()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retarget() {
+        let params = ChainParams {
+            initial_difficulty: 10,
+            target_block_time: 1000,
+            retarget_window: 10,
+            ..ChainParams::default()
+        };
+        let bits = params.initial_bits();
+        let target = Target::from_compact(bits).unwrap();
+        let retarget = |elapsed| Target::from_compact(params.retarget(bits, 10, elapsed)).unwrap();
+        let rounded = |target: Target| Target::from_compact(target.to_compact()).unwrap();
+
+        // On time
+        assert_eq!(retarget(10_000), target);
+
+        // Finer than a factor 2
+        assert_eq!(retarget(15_000), rounded(target.scale(3, 2)));
+        assert_eq!(retarget(9_000), rounded(target.scale(9, 10)));
+        assert!(retarget(9_000) < target);
+
+        // Clamped to a factor 4
+        assert_eq!(retarget(2_500).leading_zeros(), 12);
+        assert_eq!(retarget(0), retarget(2_500));
+        assert_eq!(retarget(40_000).leading_zeros(), 8);
+        assert_eq!(retarget(u64::MAX), retarget(40_000));
+
+        // Never easier than the minimum difficulty
+        let easy = Target::from_leading_zeros(2).to_compact();
+        assert_eq!(
+            Target::from_compact(params.retarget(easy, 10, u64::MAX)).unwrap().leading_zeros(),
+            MIN_DIFFICULTY
+        );
+    }
+
+    #[test]
+    fn test_pow_algorithm_names() {
+        for algorithm in PowAlgorithm::ALL {
+            assert_eq!(algorithm.to_string().parse(), Ok(algorithm));
+            let json = serde_json::to_string(&algorithm).unwrap();
+            assert_eq!(json, format!("\"{}\"", algorithm));
+        }
+        assert_eq!("sha512".parse::<PowAlgorithm>(), Err(ParsePowAlgorithmError));
+    }
+
+    #[test]
+    fn test_retarget_height() {
+        let params = ChainParams::default();
+        assert!(!params.is_retarget_height(0));
+        assert!(!params.is_retarget_height(params.retarget_window - 1));
+        assert!(params.is_retarget_height(params.retarget_window));
+        assert!(params.is_retarget_height(3 * params.retarget_window));
+    }
+}
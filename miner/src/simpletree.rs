@@ -169,7 +169,7 @@ mod tests {
 
         let deepest = root.deepest_leafs();
 
-        let expected_values = vec![
+        let expected_values = [
             Data::new(5, [4]),
             Data::new(7, [6]),
         ];
@@ -0,0 +1,238 @@
+use crate::block::{BlockHash, DanceMove};
+use crate::chain::Blockchain;
+use crate::encoding::to_hex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Windows of the dance move distribution, in blocks, when none are given.
+/// 0 stands for the whole best chain.
+pub const DEFAULT_WINDOWS: [u64; 3] = [10, 100, 0];
+
+/// Blocks mined by a key.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MinerStats {
+    /// Public key, in hex
+    pub public_key: String,
+    /// Latest alias the key mined a block of the best chain under, may be
+    /// empty
+    pub miner: String,
+    /// Blocks of the best chain
+    pub blocks: u64,
+    /// Blocks of the chain that aren't on the best chain
+    pub stale_blocks: u64,
+}
+
+/// Dance moves of the last blocks of the best chain.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DanceMoveWindow {
+    /// Number of blocks asked for, 0 for the whole best chain
+    pub window: u64,
+    /// Number of blocks counted, fewer than `window` if the chain is
+    /// shorter
+    pub blocks: u64,
+    /// Number of blocks of each dance move, all of them included
+    pub counts: BTreeMap<DanceMove, u64>,
+}
+
+/// Statistics of a chain, as served by `GET /stats`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ChainStats {
+    /// Height of the tip
+    pub height: u64,
+    /// Blocks of the chain, genesis and stale blocks included
+    pub blocks: u64,
+    /// Blocks of the chain that aren't on the best chain
+    pub stale_blocks: u64,
+    /// Blocks waiting for their parent
+    pub orphans: u64,
+    /// Average time between two blocks of the best chain, in milliseconds.
+    /// `None` for a chain of one block.
+    pub average_block_interval: Option<u64>,
+    /// Keys that mined a block, most blocks on the best chain first
+    pub miners: Vec<MinerStats>,
+    pub dance_moves: Vec<DanceMoveWindow>,
+    /// Number of branches off the best chain by depth, the number of
+    /// blocks of their longest chain
+    pub fork_depths: BTreeMap<u64, u64>,
+}
+
+impl ChainStats {
+    /// Computes the statistics of `chain`, with the dance move distribution
+    /// over each of `windows`
+    pub fn new(chain: &Blockchain, windows: &[u64]) -> Self {
+        let best: HashSet<BlockHash> = chain.best_chain().map(|node| *node.hash()).collect();
+        let tip = chain.tip();
+        let genesis = chain.genesis();
+
+        let mut miners: HashMap<&[u8], MinerStats> = HashMap::new();
+        // Newest first, so that the alias is the latest one
+        for node in chain.best_chain() {
+            let block = node.block();
+            let stats = miners.entry(&block.public_key).or_insert_with(|| MinerStats {
+                public_key: to_hex(&block.public_key),
+                miner: String::new(),
+                blocks: 0,
+                stale_blocks: 0,
+            });
+            stats.blocks += 1;
+            if stats.miner.is_empty() {
+                stats.miner = block.miner.clone();
+            }
+        }
+
+        // Branch of each stale block: the hash and height of its first block
+        let mut branches: HashMap<BlockHash, (BlockHash, u64)> = HashMap::new();
+        let mut depths: HashMap<BlockHash, u64> = HashMap::new();
+        for node in chain.iter().filter(|node| !best.contains(node.hash())) {
+            let block = node.block();
+            miners
+                .entry(&block.public_key)
+                .or_insert_with(|| MinerStats {
+                    public_key: to_hex(&block.public_key),
+                    miner: block.miner.clone(),
+                    blocks: 0,
+                    stale_blocks: 0,
+                })
+                .stale_blocks += 1;
+
+            // Parents come before their children
+            let branch = node
+                .parent()
+                .and_then(|parent| branches.get(parent))
+                .copied()
+                .unwrap_or((*node.hash(), node.height()));
+            branches.insert(*node.hash(), branch);
+            let depth = depths.entry(branch.0).or_default();
+            *depth = (*depth).max(node.height() - branch.1 + 1);
+        }
+        let mut fork_depths = BTreeMap::new();
+        for depth in depths.into_values() {
+            *fork_depths.entry(depth).or_default() += 1;
+        }
+
+        let mut miners: Vec<MinerStats> = miners.into_values().collect();
+        miners.sort_by(|a, b| {
+            (b.blocks, b.stale_blocks)
+                .cmp(&(a.blocks, a.stale_blocks))
+                .then_with(|| a.public_key.cmp(&b.public_key))
+        });
+
+        let dance_moves = windows
+            .iter()
+            .map(|&window| {
+                let len = if window == 0 { usize::MAX } else { window as usize };
+                let mut counts: BTreeMap<DanceMove, u64> =
+                    DanceMove::ALL.into_iter().map(|dancemove| (dancemove, 0)).collect();
+                for node in chain.best_chain().take(len) {
+                    *counts.entry(node.block().dancemove).or_default() += 1;
+                }
+                DanceMoveWindow {
+                    window,
+                    blocks: counts.values().sum(),
+                    counts,
+                }
+            })
+            .collect();
+
+        let elapsed = tip.block().timestamp.saturating_sub(genesis.block().timestamp);
+        ChainStats {
+            height: tip.height(),
+            blocks: chain.len() as u64,
+            stale_blocks: (chain.len() - best.len()) as u64,
+            orphans: chain.orphan_count() as u64,
+            average_block_interval: (tip.height() > 0).then(|| elapsed / tip.height()),
+            miners,
+            dance_moves,
+            fork_depths,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::params::ChainParams;
+
+    fn child(parent: &Block, miner: u8, dancemove: DanceMove, nonce: u64) -> Block {
+        let mut block = Block::new_child(
+            parent,
+            format!("miner{}", miner),
+            dancemove,
+            parent.timestamp + 1000,
+            ChainParams::default().initial_bits(),
+        );
+        block.public_key = vec![miner; 32];
+        block.nonce = nonce;
+        block
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut genesis = Block::new(vec![], "Genesis".to_string(), 0, DanceMove::Y);
+        genesis.bits = ChainParams::default().initial_bits();
+        genesis.public_key = vec![1; 32];
+        let mut chain = Blockchain::new(genesis.clone());
+
+        // genesis - a1 - a2 - a3 - a4
+        //             \- b2 - b3   \- c4
+        //        \- d1
+        let a1 = child(&genesis, 1, DanceMove::M, 1);
+        let a2 = child(&a1, 2, DanceMove::C, 2);
+        let a3 = child(&a2, 1, DanceMove::A, 3);
+        let a4 = child(&a3, 1, DanceMove::A, 4);
+        let b2 = child(&a1, 3, DanceMove::Y, 5);
+        let b3 = child(&b2, 3, DanceMove::Y, 6);
+        let c4 = child(&a3, 2, DanceMove::Y, 7);
+        let d1 = child(&genesis, 3, DanceMove::Y, 8);
+        let orphan = child(&child(&a4, 2, DanceMove::Y, 9), 2, DanceMove::Y, 10);
+        for block in [a1, a2, a3, a4.clone(), b2, b3, c4, d1, orphan] {
+            chain.add_block(block);
+        }
+        assert_eq!(chain.tip().hash(), &a4.block_hash());
+
+        let stats = ChainStats::new(&chain, &[2, 0]);
+        assert_eq!(stats.height, 4);
+        assert_eq!(stats.blocks, 9);
+        assert_eq!(stats.stale_blocks, 4);
+        assert_eq!(stats.orphans, 1);
+        assert_eq!(stats.average_block_interval, Some(1000));
+        assert_eq!(stats.fork_depths, BTreeMap::from([(1, 2), (2, 1)]));
+
+        let counts = |stats: &ChainStats| -> Vec<(String, u64, u64)> {
+            let miners = stats.miners.iter();
+            miners.map(|m| (m.miner.clone(), m.blocks, m.stale_blocks)).collect()
+        };
+        assert_eq!(
+            counts(&stats),
+            [
+                // The genesis block has the alias Genesis, but a1 is newer
+                ("miner1".to_string(), 4, 0),
+                ("miner2".to_string(), 1, 1),
+                ("miner3".to_string(), 0, 3),
+            ]
+        );
+
+        assert_eq!(stats.dance_moves[0].blocks, 2);
+        assert_eq!(stats.dance_moves[0].counts[&DanceMove::A], 2);
+        assert_eq!(stats.dance_moves[0].counts[&DanceMove::Y], 0);
+        assert_eq!(stats.dance_moves[1].window, 0);
+        assert_eq!(stats.dance_moves[1].blocks, 5);
+        assert_eq!(
+            stats.dance_moves[1].counts.values().copied().collect::<Vec<_>>(),
+            [1, 1, 1, 2]
+        );
+    }
+
+    #[test]
+    fn test_stats_of_genesis_only() {
+        let genesis = Block::new(vec![], "Genesis".to_string(), 0, DanceMove::Y);
+        let stats = ChainStats::new(&Blockchain::new(genesis), &[]);
+        assert_eq!(stats.height, 0);
+        assert_eq!(stats.blocks, 1);
+        assert_eq!(stats.average_block_interval, None);
+        assert!(stats.fork_depths.is_empty());
+        assert!(stats.dance_moves.is_empty());
+        assert_eq!(stats.miners.len(), 1);
+    }
+}
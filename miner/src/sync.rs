@@ -0,0 +1,120 @@
+use crate::block::{Block, BlockHash};
+use crate::chain::Blockchain;
+use serde::{Deserialize, Serialize};
+
+/// Largest number of blocks of a [`BlockPage`].
+pub const MAX_PAGE_SIZE: usize = 500;
+
+/// Time between two pushes of the tip to the subscribers of `GET /events`,
+/// even if it didn't change, in seconds.
+pub const TIP_EVENT_INTERVAL_SECONDS: u64 = 10;
+
+/// Blocks inserted in the chain of the server after a cursor, as served by
+/// `GET /blocks?since=<cursor>`, see [`Blockchain::inserted_since`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BlockPage {
+    /// Genesis block of the chain, the cursors only make sense for it
+    pub genesis: BlockHash,
+    /// Parents before their children
+    pub blocks: Vec<Block>,
+    /// Cursor of the block after the last one of `blocks`
+    pub next: u64,
+    /// Cursor of the next block the server will insert. There are more
+    /// blocks to get while `next` is lower.
+    pub end: u64,
+}
+
+impl BlockPage {
+    /// Page of at most `limit` blocks inserted in `chain` after the first
+    /// `since` ones
+    pub fn new(chain: &Blockchain, since: u64, limit: usize) -> Self {
+        let since = usize::try_from(since).unwrap_or(usize::MAX).min(chain.cursor());
+        let blocks: Vec<Block> = chain
+            .inserted_since(since)
+            .take(limit)
+            .map(|node| node.block().clone())
+            .collect();
+        BlockPage {
+            genesis: *chain.genesis().hash(),
+            next: (since + blocks.len()) as u64,
+            end: chain.cursor() as u64,
+            blocks,
+        }
+    }
+}
+
+/// Tip of the chain of the server, as served by `GET /tip`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Tip {
+    pub hash: BlockHash,
+    pub height: u64,
+    /// Cursor of the next block the server will insert, see [`BlockPage`]
+    pub cursor: u64,
+}
+
+impl Tip {
+    pub fn new(chain: &Blockchain) -> Self {
+        Tip {
+            hash: *chain.tip().hash(),
+            height: chain.tip().height(),
+            cursor: chain.cursor() as u64,
+        }
+    }
+}
+
+/// Message pushed by the server to the subscribers of `GET /events`, in
+/// JSON with a `type` field.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChainEvent {
+    /// A block inserted in the chain. The blocks are pushed in the order
+    /// of their insertion, parents before their children.
+    Block {
+        block: Block,
+        /// Cursor of the block after it, see [`BlockPage`]
+        cursor: u64,
+    },
+    /// The tip of the chain, when it changes and every
+    /// [`TIP_EVENT_INTERVAL_SECONDS`]
+    Tip(Tip),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::DanceMove;
+    use crate::params::ChainParams;
+
+    #[test]
+    fn test_pages() {
+        let genesis = Block::new(vec![], "Genesis".to_string(), 0, DanceMove::Y);
+        let mut chain = Blockchain::new(genesis.clone());
+        let mut parent = genesis;
+        for nonce in 1..=4 {
+            let bits = ChainParams::default().initial_bits();
+            let block = Block::new_child(&parent, String::new(), DanceMove::A, nonce, bits);
+            chain.add_block(block.clone());
+            parent = block;
+        }
+
+        let page = BlockPage::new(&chain, 0, 3);
+        assert_eq!(page.genesis, *chain.genesis().hash());
+        assert_eq!(page.blocks.len(), 3);
+        assert_eq!((page.next, page.end), (3, 5));
+        let page = BlockPage::new(&chain, page.next, 3);
+        assert_eq!(page.blocks.len(), 2);
+        assert_eq!(page.blocks[1], parent);
+        assert_eq!((page.next, page.end), (5, 5));
+        // A cursor from another chain
+        let page = BlockPage::new(&chain, 42, 3);
+        assert!(page.blocks.is_empty());
+        assert_eq!((page.next, page.end), (5, 5));
+
+        let tip = Tip::new(&chain);
+        assert_eq!(tip, Tip { hash: parent.block_hash(), height: 4, cursor: 5 });
+
+        let json = serde_json::to_value(ChainEvent::Tip(tip)).unwrap();
+        assert_eq!((&json["type"], &json["height"]), (&"tip".into(), &4.into()));
+        assert_eq!(serde_json::from_value::<ChainEvent>(json).unwrap(), ChainEvent::Tip(tip));
+    }
+}
@@ -0,0 +1,193 @@
+use ethnum::U256;
+use std::fmt;
+
+/// A proof-of-work target. A block is valid if its hash, read as a
+/// big-endian 256-bit number, is lower than or equal to its target: the
+/// lower the target, the harder the block.
+///
+/// Blocks carry their target in the compact "bits" encoding of Bitcoin's
+/// nBits: the top byte is the length in bytes of the target, the 3 other
+/// bytes its most significant bytes. The sign bit (0x00800000) must be 0.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Target(U256);
+
+impl Target {
+    /// The easiest target, met by every hash.
+    pub const MAX: Target = Target(U256::MAX);
+
+    /// Target met by the hashes starting with `zeros` bits set to 0
+    pub fn from_leading_zeros(zeros: u32) -> Self {
+        Target(U256::MAX.checked_shr(zeros).unwrap_or(U256::ZERO))
+    }
+
+    /// Number of leading bits set to 0 of the target. Every hash starting
+    /// with that many zeros meets it, up to the rounding of [`Target::to_compact`].
+    pub fn leading_zeros(&self) -> u32 {
+        self.0.leading_zeros()
+    }
+
+    /// Decodes a compact target. Returns `None` if the sign bit is set or
+    /// if the target doesn't fit in 256 bits.
+    pub fn from_compact(bits: u32) -> Option<Self> {
+        let size = bits >> 24;
+        let mantissa = bits & 0x007f_ffff;
+        if bits & 0x0080_0000 != 0 && mantissa != 0 {
+            return None;
+        }
+
+        if size <= 3 {
+            return Some(Target(U256::from(mantissa >> (8 * (3 - size)))));
+        }
+        let shift = 8 * (size - 3);
+        let value = U256::from(mantissa).checked_shl(shift)?;
+        // Reject the mantissa bits shifted out
+        if value >> shift != U256::from(mantissa) {
+            return None;
+        }
+        Some(Target(value))
+    }
+
+    /// Encodes the target in the compact form, rounding it down to its
+    /// 23 most significant bits.
+    pub fn to_compact(&self) -> u32 {
+        let mut size = (256 - self.0.leading_zeros()).div_ceil(8);
+        let mut mantissa = if size <= 3 {
+            self.0.as_u32() << (8 * (3 - size))
+        } else {
+            (self.0 >> (8 * (size - 3))).as_u32()
+        };
+        // The top bit of the mantissa is a sign bit, move it to the size
+        if mantissa & 0x0080_0000 != 0 {
+            mantissa >>= 8;
+            size += 1;
+        }
+        mantissa | (size << 24)
+    }
+
+    /// Returns true if `hash` is lower than or equal to the target
+    pub fn is_met_by(&self, hash: &[u8; 32]) -> bool {
+        U256::from_be_bytes(*hash) <= self.0
+    }
+
+    /// Expected number of hashes needed to meet the target, that is
+    /// 2^256 / (target + 1), saturating at `u128::MAX`.
+    pub fn work(&self) -> u128 {
+        if self.0 == U256::MAX {
+            return 1;
+        }
+        // 2^256 doesn't fit, but 2^256 / (t + 1) = (2^256 - 1 - t) / (t + 1) + 1
+        let work = (!self.0 / (self.0 + 1)).saturating_add(U256::ONE);
+        u128::try_from(work).unwrap_or(u128::MAX)
+    }
+
+    /// Multiplies the target by `numerator / denominator`, saturating at
+    /// [`Target::MAX`]. The lowest bits of the target may be lost when the
+    /// product doesn't fit in 256 bits.
+    pub fn scale(&self, numerator: u128, denominator: u128) -> Self {
+        let (numerator, denominator) = (U256::from(numerator), U256::from(denominator.max(1)));
+        if let Some(product) = self.0.checked_mul(numerator) {
+            return Target(product / denominator);
+        }
+        // Drop enough low bits of the target for the product to fit
+        let shift = 256 - numerator.leading_zeros() - self.0.leading_zeros();
+        let scaled = (self.0 >> shift) * numerator / denominator;
+        if scaled.leading_zeros() < shift {
+            return Target::MAX;
+        }
+        Target(scaled << shift)
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:064x}", self.0)
+    }
+}
+
+impl fmt::Debug for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Target({})", self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compact_roundtrip() {
+        // Bitcoin's genesis block target
+        let target = Target::from_compact(0x1d00ffff).unwrap();
+        assert_eq!(
+            target.to_string(),
+            "00000000ffff0000000000000000000000000000000000000000000000000000"
+        );
+        assert_eq!(target.to_compact(), 0x1d00ffff);
+
+        for bits in [0x02008000, 0x03123456, 0x04123456, 0x20123456] {
+            assert_eq!(Target::from_compact(bits).unwrap().to_compact(), bits);
+        }
+        assert_eq!(Target::from_compact(0x01123456).unwrap(), Target(U256::from(0x12u32)));
+        assert_eq!(Target::from_compact(0).unwrap().to_compact(), 0);
+
+        // Negative or too large
+        assert_eq!(Target::from_compact(0x04923456), None);
+        assert_eq!(Target::from_compact(0x21010000), None);
+        assert_eq!(Target::from_compact(0xff123456), None);
+        assert!(Target::from_compact(0x21008000).is_some());
+    }
+
+    #[test]
+    fn test_leading_zeros() {
+        for zeros in [0, 1, 10, 32, 200, 255] {
+            let target = Target::from_leading_zeros(zeros);
+            assert_eq!(target.leading_zeros(), zeros);
+            let rounded = Target::from_compact(target.to_compact()).unwrap();
+            assert_eq!(rounded.leading_zeros(), zeros);
+            assert!(rounded <= target);
+        }
+        assert_eq!(Target::from_leading_zeros(256).leading_zeros(), 256);
+        assert_eq!(Target::from_leading_zeros(300).leading_zeros(), 256);
+    }
+
+    #[test]
+    fn test_is_met_by() {
+        let target = Target::from_leading_zeros(12);
+        let mut hash = [0u8; 32];
+        assert!(target.is_met_by(&hash));
+        hash[1] = 0x0f;
+        hash[31] = 0xff;
+        assert!(target.is_met_by(&hash));
+        hash[1] = 0x10;
+        assert!(!target.is_met_by(&hash));
+        assert!(Target::MAX.is_met_by(&[0xff; 32]));
+    }
+
+    #[test]
+    fn test_work() {
+        assert_eq!(Target::MAX.work(), 1);
+        assert_eq!(Target::from_leading_zeros(1).work(), 2);
+        assert_eq!(Target::from_leading_zeros(10).work(), 1024);
+        assert_eq!(Target::from_leading_zeros(127).work(), 1 << 127);
+        assert_eq!(Target::from_leading_zeros(200).work(), u128::MAX);
+        assert_eq!(Target::from_leading_zeros(256).work(), u128::MAX);
+
+        // Rounding the target down makes it a bit harder
+        let rounded = Target::from_compact(Target::from_leading_zeros(10).to_compact()).unwrap();
+        assert_eq!(rounded.work(), 1024);
+    }
+
+    #[test]
+    fn test_scale() {
+        let target = Target::from_leading_zeros(10);
+        assert_eq!(target.scale(1, 2), Target::from_leading_zeros(11));
+        assert_eq!(target.scale(3, 3), target);
+        assert_eq!(target.scale(u128::MAX, 1), Target::MAX);
+        assert_eq!(Target::MAX.scale(1, 4), Target::from_leading_zeros(2));
+
+        // The product doesn't fit in 256 bits
+        let rounded = Target::from_compact(target.to_compact()).unwrap();
+        assert_eq!(rounded.scale(10_000, 10_000), rounded);
+        assert_eq!(rounded.scale(3 << 100, 2 << 100), rounded.scale(3, 2));
+    }
+}
@@ -0,0 +1,166 @@
+use crate::identity::PUBLIC_KEY_LEN;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// Maximum length, in bytes, of the data of a [`Payload::Data`] record.
+pub const MAX_DATA_LEN: usize = 1024;
+
+/// What a transaction does.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub enum Payload {
+    /// Moves `amount` coins from the sender to the miner key `to`.
+    Transfer { to: Vec<u8>, amount: u64 },
+    /// Records arbitrary bytes in the chain, signed by the sender.
+    Data(Vec<u8>),
+}
+
+/// A payload signed by a miner key.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub struct Transaction {
+    /// Ed25519 public key of the sender, who signs the transaction.
+    pub from: Vec<u8>,
+    /// Number of transactions of the sender before this one, so that a
+    /// transaction can't be replayed.
+    pub nonce: u64,
+    pub payload: Payload,
+    /// Ed25519 signature by `from` of [`Transaction::encode_unsigned`].
+    pub signature: Vec<u8>,
+}
+
+/// Why a transaction is malformed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidTransaction {
+    /// A key isn't 32 bytes long.
+    MalformedKey,
+    /// A transfer moves no coin.
+    ZeroAmount,
+    /// The data is longer than [`MAX_DATA_LEN`] bytes.
+    DataTooLong,
+    /// The transaction isn't signed by its sender.
+    InvalidSignature,
+    /// The nonce isn't the number of transactions the sender already sent.
+    WrongNonce,
+    /// The sender doesn't have the coins it transfers.
+    InsufficientBalance,
+    /// The balance of the receiver would overflow.
+    BalanceOverflow,
+}
+
+impl InvalidTransaction {
+    /// Short machine readable identifier of the reason
+    pub fn code(&self) -> &'static str {
+        match self {
+            InvalidTransaction::MalformedKey => "malformed_key",
+            InvalidTransaction::ZeroAmount => "zero_amount",
+            InvalidTransaction::DataTooLong => "data_too_long",
+            InvalidTransaction::InvalidSignature => "invalid_signature",
+            InvalidTransaction::WrongNonce => "wrong_nonce",
+            InvalidTransaction::InsufficientBalance => "insufficient_balance",
+            InvalidTransaction::BalanceOverflow => "balance_overflow",
+        }
+    }
+}
+
+impl fmt::Display for InvalidTransaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidTransaction::MalformedKey => write!(f, "a key isn't {} bytes long", PUBLIC_KEY_LEN),
+            InvalidTransaction::ZeroAmount => write!(f, "the transfer amount is 0"),
+            InvalidTransaction::DataTooLong => {
+                write!(f, "the data is longer than {} bytes", MAX_DATA_LEN)
+            }
+            InvalidTransaction::InvalidSignature => {
+                write!(f, "the transaction isn't signed by its sender")
+            }
+            InvalidTransaction::WrongNonce => {
+                write!(f, "the nonce isn't the next one of the sender")
+            }
+            InvalidTransaction::InsufficientBalance => {
+                write!(f, "the sender can't afford the transfer")
+            }
+            InvalidTransaction::BalanceOverflow => {
+                write!(f, "the balance of the receiver would overflow")
+            }
+        }
+    }
+}
+
+impl std::error::Error for InvalidTransaction {}
+
+/// Transaction hash, identifying it.
+pub type TxId = [u8; 32];
+
+impl Transaction {
+    /// Creates an unsigned transaction
+    pub fn new(from: Vec<u8>, nonce: u64, payload: Payload) -> Self {
+        Transaction {
+            from,
+            nonce,
+            payload,
+            signature: Vec::new(),
+        }
+    }
+
+    /// SHA-256 of the encoding of the transaction, signature included
+    pub fn txid(&self) -> TxId {
+        Sha256::digest(self.encode()).into()
+    }
+
+    /// Checks the fields that can be checked without the chain. The
+    /// signature is checked apart, see [`Transaction::verify_signature`].
+    pub fn check_structure(&self) -> Result<(), InvalidTransaction> {
+        if self.from.len() != PUBLIC_KEY_LEN {
+            return Err(InvalidTransaction::MalformedKey);
+        }
+        match &self.payload {
+            Payload::Transfer { to, .. } if to.len() != PUBLIC_KEY_LEN => {
+                Err(InvalidTransaction::MalformedKey)
+            }
+            Payload::Transfer { amount: 0, .. } => Err(InvalidTransaction::ZeroAmount),
+            Payload::Data(data) if data.len() > MAX_DATA_LEN => Err(InvalidTransaction::DataTooLong),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_structure() {
+        let key = vec![1; 32];
+        let transfer = |to: Vec<u8>, amount| {
+            Transaction::new(key.clone(), 0, Payload::Transfer { to, amount })
+        };
+        assert_eq!(transfer(vec![2; 32], 5).check_structure(), Ok(()));
+        assert_eq!(
+            transfer(vec![2; 31], 5).check_structure(),
+            Err(InvalidTransaction::MalformedKey)
+        );
+        assert_eq!(
+            transfer(vec![2; 32], 0).check_structure(),
+            Err(InvalidTransaction::ZeroAmount)
+        );
+
+        let data = |len| Transaction::new(key.clone(), 0, Payload::Data(vec![0; len]));
+        assert_eq!(data(MAX_DATA_LEN).check_structure(), Ok(()));
+        assert_eq!(
+            data(MAX_DATA_LEN + 1).check_structure(),
+            Err(InvalidTransaction::DataTooLong)
+        );
+        let anonymous = Transaction::new(vec![], 0, Payload::Data(vec![]));
+        assert_eq!(anonymous.check_structure(), Err(InvalidTransaction::MalformedKey));
+    }
+
+    #[test]
+    fn test_txid() {
+        let tx = Transaction::new(vec![1; 32], 0, Payload::Data(b"hello".to_vec()));
+        let mut signed = tx.clone();
+        signed.signature = vec![3; 64];
+        let replayed = Transaction { nonce: 1, ..tx.clone() };
+        assert_ne!(tx.txid(), signed.txid());
+        assert_ne!(tx.txid(), replayed.txid());
+    }
+}
@@ -0,0 +1,150 @@
+use miner::chain::Blockchain;
+use miner::sync::{ChainEvent, Tip};
+use rouille::websocket::Websocket;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+
+/// Largest number of events waiting to be written to a client. A client
+/// that falls that far behind is dropped, it catches up by polling.
+const MAX_PENDING_EVENTS: usize = 256;
+
+/// Clients subscribed to `GET /events`, and the events they were pushed.
+///
+/// Each client has its own thread writing the events to its WebSocket, so
+/// pushing an event never waits for a client.
+#[derive(Default)]
+pub struct Subscribers {
+    /// Events in JSON, to the writer thread of each client
+    clients: Vec<SyncSender<Arc<str>>>,
+    /// Cursor of the next block to push
+    cursor: usize,
+    /// Last tip pushed
+    tip: Option<Tip>,
+}
+
+impl Subscribers {
+    /// Adds a client, and pushes it the current tip so that it can catch up
+    pub fn add(&mut self, mut websocket: Websocket) {
+        let messages = self.subscribe();
+        thread::spawn(move || {
+            for message in messages {
+                if websocket.send_text(&message).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+
+    /// Adds a client, which gets the events in JSON from the returned
+    /// channel, starting with the current tip
+    fn subscribe(&mut self) -> Receiver<Arc<str>> {
+        let (sender, messages) = mpsc::sync_channel(MAX_PENDING_EVENTS);
+        if let Some(tip) = self.tip {
+            sender.try_send(to_json(&ChainEvent::Tip(tip))).ok();
+        }
+        self.clients.push(sender);
+        messages
+    }
+
+    /// Returns the events to push for the blocks inserted in `chain` since
+    /// the last call, then for its tip if it changed. Pushing them doesn't
+    /// need the chain any more.
+    pub fn update(&mut self, chain: &Blockchain) -> Vec<ChainEvent> {
+        let mut events: Vec<ChainEvent> = chain
+            .inserted_since(self.cursor)
+            .zip(self.cursor + 1..)
+            .map(|(node, cursor)| ChainEvent::Block {
+                block: node.block().clone(),
+                cursor: cursor as u64,
+            })
+            .collect();
+        self.cursor = chain.cursor();
+
+        let tip = Tip::new(chain);
+        if self.tip.map(|tip| tip.hash) != Some(tip.hash) {
+            events.push(ChainEvent::Tip(tip));
+        }
+        self.tip = Some(tip);
+        events
+    }
+
+    /// Pushes the last tip again, so that the clients can tell the
+    /// connection is still alive
+    pub fn keepalive(&mut self) {
+        if let Some(tip) = self.tip {
+            self.push(&[ChainEvent::Tip(tip)]);
+        }
+    }
+
+    /// Pushes the events to every client, and drops the disconnected ones
+    /// and the ones too slow to keep up
+    pub fn push(&mut self, events: &[ChainEvent]) {
+        for message in events.iter().map(to_json) {
+            self.clients.retain(|client| match client.try_send(message.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => false,
+            });
+        }
+    }
+}
+
+fn to_json(event: &ChainEvent) -> Arc<str> {
+    serde_json::to_string(event).expect("events serialize to JSON").into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miner::block::{Block, DanceMove};
+    use miner::chain::AddBlockOutcome;
+
+    fn event(message: Arc<str>) -> ChainEvent {
+        serde_json::from_str(&message).unwrap()
+    }
+
+    #[test]
+    fn test_push() {
+        let genesis = Block::new(vec![], "Genesis".to_string(), 0, DanceMove::Y);
+        let mut chain = Blockchain::new(genesis.clone());
+        let mut subscribers = Subscribers::default();
+        let events = subscribers.update(&chain);
+        assert_eq!(events.len(), 2);
+        subscribers.push(&events);
+
+        let staying = subscribers.subscribe();
+        let leaving = subscribers.subscribe();
+        assert_eq!(event(staying.recv().unwrap()), ChainEvent::Tip(Tip::new(&chain)));
+
+        // A client disconnects before a push
+        drop(leaving);
+        let bits = chain.next_bits(chain.genesis());
+        let block = Block::new_child(&genesis, "miner1".to_string(), DanceMove::A, genesis.timestamp + 1, bits);
+        assert_eq!(chain.add_block(block.clone()), AddBlockOutcome::Inserted { tip_changed: true });
+        let events = subscribers.update(&chain);
+        subscribers.push(&events);
+        assert_eq!(subscribers.clients.len(), 1);
+        assert_eq!(event(staying.recv().unwrap()), ChainEvent::Block { block, cursor: 2 });
+        assert_eq!(event(staying.recv().unwrap()), ChainEvent::Tip(Tip::new(&chain)));
+
+        // Nothing new, only the keepalive
+        assert!(subscribers.update(&chain).is_empty());
+        subscribers.keepalive();
+        assert_eq!(event(staying.recv().unwrap()), ChainEvent::Tip(Tip::new(&chain)));
+        assert!(staying.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_slow_client_is_dropped() {
+        let genesis = Block::new(vec![], "Genesis".to_string(), 0, DanceMove::Y);
+        let mut subscribers = Subscribers::default();
+        subscribers.update(&Blockchain::new(genesis));
+        let slow = subscribers.subscribe();
+        for _ in 0..MAX_PENDING_EVENTS {
+            subscribers.keepalive();
+        }
+        assert_eq!(subscribers.clients.len(), 0);
+        // It still gets what was pushed before
+        assert_eq!(slow.iter().count(), MAX_PENDING_EVENTS);
+    }
+}
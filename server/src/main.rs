@@ -2,11 +2,31 @@
 extern crate rouille;
 
 use clap::Parser;
+use events::Subscribers;
 use miner::block::Block;
-use miner::block::BlockIdHasher;
-use miner::block::DIFFICULTY;
-use std::collections::HashMap;
-use std::sync::Mutex;
+use miner::chain::{check_structure, AddBlockOutcome, Blockchain};
+use miner::encoding::{parse_hex, DecodeError};
+use miner::ledger::Balance;
+use miner::lightclient::InclusionProof;
+use miner::mempool::Mempool;
+use miner::params::{ChainParams, PowAlgorithm};
+use miner::stats::{ChainStats, DEFAULT_WINDOWS};
+use miner::sync::{BlockPage, Tip, MAX_PAGE_SIZE, TIP_EVENT_INTERVAL_SECONDS};
+use miner::transaction::Transaction;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use storage::{BlockStorage, FileStorage, MemoryStorage};
+
+mod events;
+mod storage;
+
+/// Largest body accepted for a block in the binary encoding, in bytes.
+const MAX_ENCODED_BLOCK_SIZE: u64 = 4 * 1024 * 1024;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -15,60 +35,560 @@ struct Args {
     address: Option<String>,
     #[arg(short, default_value_t = 8080)]
     port: u16,
-    #[arg(short, default_value_t = DIFFICULTY)]
+    /// Difficulty of the genesis block, retargeted afterwards
+    #[arg(short, default_value_t = ChainParams::default().initial_difficulty)]
     difficulty: u32,
+    /// Time between two blocks the difficulty retargeting aims for, in milliseconds
+    #[arg(long, default_value_t = ChainParams::default().target_block_time)]
+    target_block_time: u64,
+    /// Number of blocks between two difficulty adjustments
+    #[arg(long, default_value_t = ChainParams::default().retarget_window)]
+    retarget_window: u64,
+    /// Proof of work hash function: sha256, double-sha256, blake3 or scrypt
+    #[arg(long, default_value_t = PowAlgorithm::default())]
+    pow: PowAlgorithm,
+    /// Coins credited to the miner of each block
+    #[arg(long, default_value_t = ChainParams::default().block_reward)]
+    block_reward: u64,
+    /// Directory where blocks are persisted. Blocks are only kept in memory if not set.
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+}
+
+/// Body of the responses to `/postblock` and `/tx`.
+#[derive(Serialize)]
+struct PostResponse {
+    /// "accepted", "orphaned" or "rejected"
+    status: &'static str,
+    /// Machine readable reason of a rejection
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+fn accepted(status: &'static str, status_code: u16) -> rouille::Response {
+    rouille::Response::json(&PostResponse {
+        status,
+        code: None,
+        message: None,
+    })
+    .with_status_code(status_code)
+}
+
+fn rejected(code: &'static str, message: impl Into<String>) -> rouille::Response {
+    rouille::Response::json(&PostResponse {
+        status: "rejected",
+        code: Some(code),
+        message: Some(message.into()),
+    })
+    .with_status_code(400)
 }
 
-// Our database of blocks. If it dies, it dies.
-type BlockHashMap<V> = HashMap<u64, V, BlockIdHasher>;
+/// Response to a block given to the chain
+fn added(outcome: AddBlockOutcome) -> rouille::Response {
+    match outcome {
+        AddBlockOutcome::Inserted { .. } => accepted("accepted", 200),
+        AddBlockOutcome::Orphaned => accepted("orphaned", 202),
+        AddBlockOutcome::Duplicate => rejected("duplicate_block", "Block already exists"),
+        AddBlockOutcome::Invalid(reason) => rejected(reason.code(), reason.to_string()),
+    }
+}
+
+/// Our database of blocks.
+struct Database {
+    /// Empty until a genesis block is posted.
+    chain: Option<Blockchain>,
+    /// Every block inserted in `chain` is written here, parents first.
+    storage: Box<dyn BlockStorage>,
+    /// Transactions waiting to be mined on the tip of `chain`.
+    mempool: Mempool,
+}
+
+impl Database {
+    /// Rebuilds the chain from the blocks of the storage
+    fn open(mut storage: Box<dyn BlockStorage>, params: ChainParams) -> std::io::Result<Self> {
+        let mut blocks = storage.load()?.into_iter();
+        let chain = blocks.next().map(|genesis| {
+            let mut chain = Blockchain::new(genesis).with_params(params);
+            for block in blocks {
+                let hash = block.block_hash();
+                if let AddBlockOutcome::Invalid(reason) = chain.add_block(block) {
+                    eprintln!("Dropping the stored block {}: {}", hash, reason);
+                }
+            }
+            chain
+        });
+        let mempool = chain.as_ref().map(Mempool::new).unwrap_or_default();
+        Ok(Database { chain, storage, mempool })
+    }
+}
+
+/// Reads a block or a transaction, either in JSON or in its binary
+/// encoding, depending on the Content-Type of the request
+fn read_body<T: DeserializeOwned>(
+    request: &rouille::Request,
+    decode: fn(&[u8]) -> Result<T, DecodeError>,
+) -> Result<T, rouille::Response> {
+    match request.header("Content-Type") {
+        Some("application/json") => rouille::input::json_input(request).map_err(|e| {
+            eprintln!("JSON parse error: {:?}", e);
+            rejected("invalid_json", "Invalid JSON format")
+        }),
+        Some("application/octet-stream") => {
+            let mut bytes = Vec::new();
+            let Some(body) = request.data() else {
+                return Err(rejected("invalid_encoding", "The body was already read"));
+            };
+            if let Err(e) = body.take(MAX_ENCODED_BLOCK_SIZE + 1).read_to_end(&mut bytes) {
+                return Err(rejected("invalid_encoding", e.to_string()));
+            }
+            if bytes.len() as u64 > MAX_ENCODED_BLOCK_SIZE {
+                return Err(rejected("invalid_encoding", "The body is too large"));
+            }
+            decode(&bytes).map_err(|e| rejected("invalid_encoding", e.to_string()))
+        }
+        _ => Err(rejected(
+            "invalid_content_type",
+            "Expected Content-Type: application/json or application/octet-stream",
+        )),
+    }
+}
+
+fn post_transaction(request: &rouille::Request, db: &Mutex<Database>) -> rouille::Response {
+    let transaction = match read_body(request, Transaction::decode) {
+        Ok(transaction) => transaction,
+        Err(response) => return response,
+    };
+    match db.lock().unwrap().mempool.add(transaction) {
+        Ok(()) => accepted("accepted", 200),
+        Err(reason) => rejected(reason.code(), reason.to_string()),
+    }
+}
+
+fn post_block(request: &rouille::Request, db: &Mutex<Database>, params: ChainParams) -> rouille::Response {
+    let block = match read_body(request, Block::decode) {
+        Ok(block) => block,
+        Err(response) => return response,
+    };
+
+    if let Err(reason) = check_structure(&block).and_then(|()| block.verify_signatures()) {
+        return rejected(reason.code(), reason.to_string());
+    }
+
+    // check_structure made sure the target is valid, and the chain checks
+    // it's the required one
+    let target = block.target().unwrap();
+    if !block.pow_check(&block.pow_hash(params.pow.proof_of_work()), &target) {
+        return rejected("invalid_proof_of_work", "Invalid proof-of-work");
+    }
+
+    let mut db = db.lock().unwrap();
+    let db = &mut *db;
+    match &db.chain {
+        None => {
+            if !block.is_genesis(params.initial_difficulty) {
+                return rejected("unknown_parent", "No genesis block yet, the first block must be one");
+            }
+            if block.bits != params.initial_bits() {
+                return rejected(
+                    "wrong_difficulty",
+                    format!("The genesis bits must be {:#010x}", params.initial_bits()),
+                );
+            }
+        }
+        Some(_) if block.parent_hash.is_empty() && !block.is_genesis(params.initial_difficulty) => {
+            return rejected("invalid_genesis", "A block without parent must be a genesis block");
+        }
+        Some(chain) => {
+            if let Err(outcome) = chain.check_block(&block) {
+                return added(outcome);
+            }
+        }
+    }
+
+    // Only the blocks inserted in the chain are stored, orphans may never
+    // be. The block is stored before adding it, so that the chain in
+    // memory never has a block that would be lost on restart.
+    let hash = block.block_hash();
+    let orphan = match (&db.chain, block.parent()) {
+        (Some(chain), Some(parent)) => !chain.contains(&parent),
+        _ => false,
+    };
+    if !orphan {
+        if let Err(e) = db.storage.append(&block) {
+            return storage_error(e);
+        }
+    }
+
+    match db.chain.as_mut() {
+        None => {
+            let chain = Blockchain::new(block).with_params(params);
+            db.mempool.update(&chain);
+            db.chain = Some(chain);
+            accepted("accepted", 200)
+        }
+        Some(chain) => {
+            let cursor = chain.cursor();
+            let outcome = chain.add_block(block);
+            if outcome == (AddBlockOutcome::Inserted { tip_changed: true }) {
+                db.mempool.update(chain);
+            }
+            // The orphans adopted along with the block
+            for node in chain.inserted_since(cursor).filter(|node| *node.hash() != hash) {
+                if let Err(e) = db.storage.append(node.block()) {
+                    return storage_error(e);
+                }
+            }
+            added(outcome)
+        }
+    }
+}
+
+fn storage_error(e: std::io::Error) -> rouille::Response {
+    eprintln!("Failed to store block: {:?}", e);
+    rouille::Response::json(&PostResponse {
+        status: "rejected",
+        code: Some("storage_error"),
+        message: Some(e.to_string()),
+    })
+    .with_status_code(500)
+}
 
 fn main() {
     let args = Args::parse();
     let address = args.address.unwrap_or("0.0.0.0".to_string());
-    let db = Mutex::new(BlockHashMap::<Block>::default());
+    let params = ChainParams {
+        initial_difficulty: args.difficulty,
+        target_block_time: args.target_block_time,
+        retarget_window: args.retarget_window,
+        pow: args.pow,
+        block_reward: args.block_reward,
+    };
+    let storage: Box<dyn BlockStorage> = match &args.data_dir {
+        Some(data_dir) => {
+            let storage = FileStorage::open(data_dir).expect("Failed to open the data directory");
+            if let Err(e) = storage.check_params(&params) {
+                eprintln!("Refusing to start: {}", e);
+                std::process::exit(1);
+            }
+            println!("Storing blocks in {:?}", storage.path());
+            Box::new(storage)
+        }
+        None => Box::new(MemoryStorage::default()),
+    };
+    let db = Mutex::new(Database::open(storage, params).expect("Failed to load the stored blocks"));
+    let subscribers = Arc::new(Mutex::new(Subscribers::default()));
+    if let Some(chain) = &db.lock().unwrap().chain {
+        println!("Loaded {} blocks, tip at height {}", chain.len(), chain.tip().height());
+        subscribers.lock().unwrap().update(chain);
+    }
+
+    // Push the tip regularly, so that the subscribers can tell they are
+    // still connected
+    let keepalive = subscribers.clone();
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(TIP_EVENT_INTERVAL_SECONDS));
+        keepalive.lock().unwrap().keepalive();
+    });
 
     println!("Now listening on {:?}:{:?}", address, args.port);
 
     rouille::start_server(format!("{}:{}", address, args.port), move |request| {
-        rouille::log(request, std::io::stdout(), || {
-            router!(request,
-                (GET) (/blocks) => {
-                    let db = db.lock().unwrap();
-                    rouille::Response::json(&db.values().cloned().collect::<Vec<Block>>())
-                },
-
-                (POST) (/postblock) => {
-                    if request.header("Content-Type") != Some("application/json") {
-                        return rouille::Response::text("Expected Content-Type: application/json")
-                            .with_status_code(400);
-                    }
-
-                    let block: Block = match rouille::input::json_input(request) {
-                        Ok(block) => block,
-                        Err(e) => {
-                            eprintln!("JSON parse error: {:?}", e);
-                            return rouille::Response::text("Invalid JSON format")
-                                .with_status_code(400);
+        rouille::log(request, std::io::stdout(), || handle(request, &db, &subscribers, params))
+    });
+}
+
+/// Answers a request to the API
+fn handle(
+    request: &rouille::Request,
+    db: &Mutex<Database>,
+    subscribers: &Arc<Mutex<Subscribers>>,
+    params: ChainParams,
+) -> rouille::Response {
+    router!(request,
+        (GET) (/blocks) => {
+            let db = db.lock().unwrap();
+            // ?since=CURSOR only returns the blocks inserted after the cursor,
+            // at most ?limit=N of them
+            if let Some(since) = request.get_param("since") {
+                let limit = request.get_param("limit").and_then(|limit| limit.parse().ok());
+                let limit = limit.unwrap_or(MAX_PAGE_SIZE).min(MAX_PAGE_SIZE);
+                return match (since.parse(), &db.chain) {
+                    (Err(_), _) => rouille::Response::empty_400(),
+                    (Ok(_), None) => rouille::Response::empty_404(),
+                    (Ok(since), Some(chain)) => rouille::Response::json(&BlockPage::new(chain, since, limit)),
+                };
+            }
+            let blocks: Vec<Block> = db
+                .chain
+                .iter()
+                .flat_map(|chain| chain.iter().map(|node| node.block().clone()))
+                .collect();
+            rouille::Response::json(&blocks)
+        },
+
+        (GET) (/blocks/{hash: String}) => {
+            let db = db.lock().unwrap();
+            let node = hash.parse().ok().zip(db.chain.as_ref()).and_then(|(hash, chain)| chain.get(&hash));
+            match node {
+                Some(node) => rouille::Response::json(node.block()),
+                None => rouille::Response::empty_404(),
+            }
+        },
+
+        (GET) (/tip) => {
+            let db = db.lock().unwrap();
+            match &db.chain {
+                Some(chain) => rouille::Response::json(&Tip::new(chain)),
+                None => rouille::Response::empty_404(),
+            }
+        },
+
+        (GET) (/events) => {
+            // WebSocket pushing the new blocks and tips, see ChainEvent
+            match rouille::websocket::start(request, None::<&str>) {
+                Ok((response, websocket)) => {
+                    let subscribers = subscribers.clone();
+                    thread::spawn(move || {
+                        if let Ok(websocket) = websocket.recv() {
+                            subscribers.lock().unwrap().add(websocket);
                         }
-                    };
+                    });
+                    response
+                }
+                Err(_) => rouille::Response::empty_400(),
+            }
+        },
 
-                    let mut db = db.lock().unwrap();
-                    if db.contains_key(&block.nonce) {
-                        return rouille::Response::text("Block already exists").with_status_code(400);
-                    }
+        (GET) (/headers) => {
+            // Headers of the best chain from the height `from`, genesis first
+            let from: u64 = request.get_param("from").and_then(|from| from.parse().ok()).unwrap_or(0);
+            let db = db.lock().unwrap();
+            let mut headers: Vec<Block> = db
+                .chain
+                .iter()
+                .flat_map(|chain| {
+                    chain
+                        .best_chain()
+                        .take_while(|node| node.height() >= from)
+                        .map(|node| node.block().header())
+                })
+                .collect();
+            headers.reverse();
+            rouille::Response::json(&headers)
+        },
 
+        (GET) (/proof/{txid: String}) => {
+            let db = db.lock().unwrap();
+            let proof = parse_hex(&txid).zip(db.chain.as_ref()).and_then(|(txid, chain)| {
+                let (node, index) = chain.find_transaction(&txid)?;
+                InclusionProof::new(node.block(), index)
+            });
+            match proof {
+                Some(proof) => rouille::Response::json(&proof),
+                None => rouille::Response::empty_404(),
+            }
+        },
 
+        (GET) (/balances) => {
+            let db = db.lock().unwrap();
+            let balances: Vec<Balance> =
+                db.chain.as_ref().map(Blockchain::balances).unwrap_or_default();
+            rouille::Response::json(&balances)
+        },
 
-                    if !block.pow_check(&block.hash_block(),  args.difficulty) {
-                        return rouille::Response::text("Invalid proof-of-work").with_status_code(400);
-                    }
+        (GET) (/stats) => {
+            // ?windows=10,100 sets the windows of the dance move distribution
+            let windows: Result<Vec<u64>, _> = match request.get_param("windows") {
+                Some(windows) => windows.split(',').map(str::parse).collect(),
+                None => Ok(DEFAULT_WINDOWS.to_vec()),
+            };
+            let db = db.lock().unwrap();
+            match (windows, &db.chain) {
+                (Err(_), _) => rouille::Response::empty_400(),
+                (Ok(_), None) => rouille::Response::empty_404(),
+                (Ok(windows), Some(chain)) => rouille::Response::json(&ChainStats::new(chain, &windows)),
+            }
+        },
 
-                    db.insert(block.nonce, block);
-                    rouille::Response::text("Block accepted").with_status_code(200)
-                },
+        (GET) (/template) => {
+            let db = db.lock().unwrap();
+            match &db.chain {
+                Some(chain) => rouille::Response::json(&db.mempool.template(chain)),
+                None => rouille::Response::empty_404(),
+            }
+        },
 
-                _ => rouille::Response::empty_404()
-            )
-        })
-    });
+        (POST) (/tx) => {
+            post_transaction(request, db)
+        },
+
+        (GET) (/params) => {
+            rouille::Response::json(&params)
+        },
+
+        (POST) (/postblock) => {
+            let response = post_block(request, db, params);
+            if response.is_success() {
+                let db = db.lock().unwrap();
+                if let Some(chain) = &db.chain {
+                    // Lock the subscribers before releasing the chain, so that
+                    // the events are pushed in the order of the blocks
+                    let mut subscribers = subscribers.lock().unwrap();
+                    let events = subscribers.update(chain);
+                    drop(db);
+                    subscribers.push(&events);
+                }
+            }
+            response
+        },
+
+        _ => rouille::Response::empty_404()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miner::block::DanceMove;
+    use miner::identity::MinerKey;
+
+    /// Storage of a full disk
+    struct FailingStorage;
+
+    impl BlockStorage for FailingStorage {
+        fn append(&mut self, _: &Block) -> std::io::Result<()> {
+            Err(std::io::Error::other("no space left"))
+        }
+
+        fn load(&mut self) -> std::io::Result<Vec<Block>> {
+            Ok(vec![])
+        }
+    }
+
+    fn params() -> ChainParams {
+        ChainParams {
+            initial_difficulty: 4,
+            ..ChainParams::default()
+        }
+    }
+
+    fn genesis() -> Block {
+        mine(Block::new(vec![], "Genesis".to_string(), 0, DanceMove::Y))
+    }
+
+    fn child(parent: &Block) -> Block {
+        let bits = params().initial_bits();
+        mine(Block::new_child(parent, "miner1".to_string(), DanceMove::A, parent.timestamp + 1, bits))
+    }
+
+    /// Signs the block and finds its proof of work
+    fn mine(mut block: Block) -> Block {
+        let key = MinerKey::from_seed([1; 32]);
+        block.bits = params().initial_bits();
+        block.public_key = key.public_key().to_vec();
+        let target = block.target().unwrap();
+        let pow = params().pow.proof_of_work();
+        while !block.pow_check(&block.pow_hash(pow), &target) {
+            block.nonce += 1;
+        }
+        block.sign(&key);
+        block
+    }
+
+    fn post(db: &Mutex<Database>, block: &Block) -> rouille::Response {
+        let request = rouille::Request::fake_http(
+            "POST",
+            "/postblock",
+            vec![("Content-Type".to_string(), "application/json".to_string())],
+            serde_json::to_vec(block).unwrap(),
+        );
+        post_block(&request, db, params())
+    }
+
+    fn get(db: &Mutex<Database>, url: &str) -> rouille::Response {
+        let request = rouille::Request::fake_http("GET", url, vec![], vec![]);
+        handle(&request, db, &Arc::default(), params())
+    }
+
+    fn body<T: DeserializeOwned>(response: rouille::Response) -> T {
+        let (mut reader, _) = response.data.into_reader_and_size();
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn empty_db() -> Mutex<Database> {
+        Mutex::new(Database::open(Box::new(MemoryStorage::default()), params()).unwrap())
+    }
+
+    #[test]
+    fn test_post_block_is_stored_first() {
+        let genesis = genesis();
+
+        let db = Mutex::new(Database::open(Box::new(FailingStorage), params()).unwrap());
+        assert_eq!(post(&db, &genesis).status_code, 500);
+        assert!(db.lock().unwrap().chain.is_none());
+
+        let db = empty_db();
+        assert_eq!(post(&db, &genesis).status_code, 200);
+        assert_eq!(post(&db, &genesis).status_code, 400);
+        let mut db = db.into_inner().unwrap();
+        assert_eq!(db.storage.load().unwrap(), vec![genesis]);
+        assert!(db.chain.is_some());
+    }
+
+    #[test]
+    fn test_orphans_are_not_stored() {
+        let db = empty_db();
+        let genesis = genesis();
+        let b1 = child(&genesis);
+        let b2 = child(&b1);
+        post(&db, &genesis);
+        assert_eq!(post(&db, &b2).status_code, 202);
+        assert_eq!(db.lock().unwrap().storage.load().unwrap(), std::slice::from_ref(&genesis));
+
+        // Stored once its parent is
+        assert_eq!(post(&db, &b1).status_code, 200);
+        let mut db = db.into_inner().unwrap();
+        assert_eq!(db.storage.load().unwrap(), [genesis, b1, b2]);
+        assert_eq!(db.chain.unwrap().cursor(), 3);
+    }
+
+    #[test]
+    fn test_stats_errors() {
+        let db = empty_db();
+        assert_eq!(get(&db, "/stats").status_code, 404);
+
+        post(&db, &genesis());
+        assert_eq!(get(&db, "/stats?windows=10,x").status_code, 400);
+        assert_eq!(get(&db, "/stats?windows=").status_code, 400);
+        let stats: ChainStats = body(get(&db, "/stats?windows=5"));
+        assert_eq!(stats.blocks, 1);
+        assert_eq!(stats.dance_moves[0].blocks, 1);
+    }
+
+    #[test]
+    fn test_sync_errors() {
+        let db = empty_db();
+        assert_eq!(get(&db, "/blocks?since=0").status_code, 404);
+        assert_eq!(get(&db, "/tip").status_code, 404);
+
+        let genesis = genesis();
+        post(&db, &genesis);
+        for cursor in ["", "-1", "x", "1.5"] {
+            assert_eq!(get(&db, &format!("/blocks?since={}", cursor)).status_code, 400);
+        }
+        // A cursor past the end gives an empty page
+        let page: BlockPage = body(get(&db, "/blocks?since=99&limit=x"));
+        assert_eq!((page.blocks.len(), page.next, page.end), (0, 1, 1));
+
+        assert_eq!(get(&db, &format!("/blocks/{}", "00".repeat(32))).status_code, 404);
+        assert_eq!(get(&db, "/blocks/xyz").status_code, 404);
+        let found: Block = body(get(&db, &format!("/blocks/{}", genesis.block_hash())));
+        assert_eq!(found, genesis);
+        let tip: Tip = body(get(&db, "/tip"));
+        assert_eq!(tip.hash, genesis.block_hash());
+    }
 }
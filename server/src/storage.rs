@@ -0,0 +1,224 @@
+use miner::block::Block;
+use miner::params::ChainParams;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Name of the block log inside the data directory.
+const LOG_FILE_NAME: &str = "blocks.log";
+
+/// Name of the chain parameters the blocks were accepted with, inside the
+/// data directory.
+const PARAMS_FILE_NAME: &str = "params.json";
+
+/// Where the server keeps the blocks it accepted.
+pub trait BlockStorage: Send {
+    /// Durably stores a block. Once this returns `Ok`, the block survives a crash.
+    fn append(&mut self, block: &Block) -> io::Result<()>;
+
+    /// Returns all the stored blocks, in the order they were appended.
+    fn load(&mut self) -> io::Result<Vec<Block>>;
+}
+
+/// Keeps the blocks in memory. If it dies, it dies.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    blocks: Vec<Block>,
+}
+
+impl BlockStorage for MemoryStorage {
+    fn append(&mut self, block: &Block) -> io::Result<()> {
+        self.blocks.push(block.clone());
+        Ok(())
+    }
+
+    fn load(&mut self) -> io::Result<Vec<Block>> {
+        Ok(self.blocks.clone())
+    }
+}
+
+/// Append-only log of blocks, one JSON block per line.
+///
+/// Every append is flushed to disk before returning. A crash in the middle
+/// of an append leaves an incomplete last line, which is dropped on load.
+#[derive(Debug)]
+pub struct FileStorage {
+    path: PathBuf,
+    file: File,
+}
+
+impl FileStorage {
+    /// Opens (or creates) the block log of the given data directory
+    pub fn open(data_dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(data_dir)?;
+        let path = data_dir.join(LOG_FILE_NAME);
+        let created = !path.exists();
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+        if created {
+            // The new entry of the directory must survive a crash too
+            File::open(data_dir)?.sync_all()?;
+        }
+        Ok(FileStorage { path, file })
+    }
+
+    /// Path of the block log
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Checks that the stored blocks were accepted with `params`, since
+    /// other parameters would reject some of them. The parameters are
+    /// stored along with the blocks the first time.
+    pub fn check_params(&self, params: &ChainParams) -> io::Result<()> {
+        let data_dir = self.path.parent().unwrap_or(Path::new("."));
+        let path = data_dir.join(PARAMS_FILE_NAME);
+        match fs::read(&path) {
+            Ok(stored) => {
+                let stored: ChainParams = serde_json::from_slice(&stored)?;
+                if stored != *params {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("the blocks of {:?} were accepted with other parameters: {:?}", data_dir, stored),
+                    ));
+                }
+                Ok(())
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let tmp = data_dir.join(format!("{}.tmp", PARAMS_FILE_NAME));
+                let mut file = File::create(&tmp)?;
+                serde_json::to_writer(&mut file, params)?;
+                file.sync_data()?;
+                fs::rename(&tmp, &path)?;
+                File::open(data_dir)?.sync_all()
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl BlockStorage for FileStorage {
+    fn append(&mut self, block: &Block) -> io::Result<()> {
+        let mut line = serde_json::to_vec(block)?;
+        line.push(b'\n');
+        // A single write, so a crash can only leave the last line incomplete
+        self.file.write_all(&line)?;
+        self.file.sync_data()
+    }
+
+    fn load(&mut self) -> io::Result<Vec<Block>> {
+        self.file.seek(SeekFrom::Start(0))?;
+        let mut reader = BufReader::new(&self.file);
+        let mut blocks = Vec::new();
+        let mut valid_len = 0;
+        let mut line = Vec::new();
+
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line)?;
+            if read == 0 {
+                break;
+            }
+            // Only the last line can be incomplete, because of a crash
+            // during an append.
+            if line.last() != Some(&b'\n') {
+                eprintln!("Dropping an incomplete block at the end of {:?}", self.path);
+                break;
+            }
+            let block = serde_json::from_slice(&line).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("corrupted block log {:?}: {}", self.path, e),
+                )
+            })?;
+            blocks.push(block);
+            valid_len += read as u64;
+        }
+
+        // Remove the incomplete line so the next append starts on a fresh line
+        self.file.set_len(valid_len)?;
+        self.file.sync_data()?;
+        Ok(blocks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miner::block::DanceMove;
+
+    fn create_test_blocks() -> Vec<Block> {
+        let genesis = Block::new(vec![], "Genesis".to_string(), 0, DanceMove::Y);
+        let block1 = Block::new(genesis.hash_block().to_vec(), "miner1".to_string(), 42, DanceMove::C);
+        vec![genesis, block1]
+    }
+
+    #[test]
+    fn test_memory_storage() {
+        let mut storage = MemoryStorage::default();
+        for block in create_test_blocks() {
+            storage.append(&block).unwrap();
+        }
+        assert_eq!(storage.load().unwrap(), create_test_blocks());
+    }
+
+    #[test]
+    fn test_file_storage_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut storage = FileStorage::open(dir.path()).unwrap();
+            assert!(storage.load().unwrap().is_empty());
+            for block in create_test_blocks() {
+                storage.append(&block).unwrap();
+            }
+        }
+
+        let mut storage = FileStorage::open(dir.path()).unwrap();
+        assert_eq!(storage.load().unwrap(), create_test_blocks());
+    }
+
+    #[test]
+    fn test_file_storage_params() {
+        let dir = tempfile::tempdir().unwrap();
+        let params = ChainParams::default();
+        FileStorage::open(dir.path()).unwrap().check_params(&params).unwrap();
+
+        let storage = FileStorage::open(dir.path()).unwrap();
+        storage.check_params(&params).unwrap();
+        let other = ChainParams {
+            block_reward: params.block_reward + 1,
+            ..params
+        };
+        let error = storage.check_params(&other).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_file_storage_incomplete_append() {
+        let dir = tempfile::tempdir().unwrap();
+        let blocks = create_test_blocks();
+        let path = {
+            let mut storage = FileStorage::open(dir.path()).unwrap();
+            storage.append(&blocks[0]).unwrap();
+            storage.path().to_path_buf()
+        };
+
+        // Simulate a crash in the middle of the second append
+        let line = serde_json::to_vec(&blocks[1]).unwrap();
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&line[..line.len() / 2])
+            .unwrap();
+
+        let mut storage = FileStorage::open(dir.path()).unwrap();
+        assert_eq!(storage.load().unwrap(), blocks[..1]);
+
+        storage.append(&blocks[1]).unwrap();
+        assert_eq!(storage.load().unwrap(), blocks);
+    }
+}